@@ -1,8 +1,10 @@
 #version 450
 
-layout(location=0) out vec4 f_color;
+// computed per boid in boids.vert
+layout(location = 0) flat in vec4 v_color;
 
+layout(location=0) out vec4 f_color;
 
 void main() {
-    f_color = vec4(1.0);
-}
\ No newline at end of file
+    f_color = v_color;
+}
@@ -1,55 +1,144 @@
 #version 450
 
+// MAX_SPECIES in species.rs
+#define MAX_SPECIES 8
+
+// ColorMode and Colormap in coloring.rs
+#define COLOR_SPECIES 0
+#define COLOR_SPEED 1
+#define COLOR_HEADING 2
+#define COLOR_NEIGHBOURS 3
+#define COLOR_ID 4
+#define COLOR_CLUSTER 5
+
+#define COLORMAP_VIRIDIS 0
+#define COLORMAP_MAGMA 1
+
 layout(location=0) in vec4 a_position;
 
 layout(location = 1) in vec4 a_translation;
-layout(location = 2) in vec4 a_rotation;
+layout(location = 2) in vec4 a_velocity;
+layout(location = 3) in vec4 a_rotation;
+layout(location = 5) in uint a_species;
+layout(location = 6) in uint a_neighbours;
+layout(location = 7) in uint a_cluster;
+layout(location = 9) in uint a_index;
+// the boid at the previous step
+layout(location = 10) in vec4 a_previous_translation;
+layout(location = 11) in vec4 a_previous_rotation;
+
+layout(location = 0) flat out vec4 v_color;
 
 layout(set=0, binding=0) 
 uniform Uniforms {
     mat4 u_view_proj;
 };
 
-#define eps 0.005
+layout(set = 1, binding = 0)
+uniform SpeciesColors {
+    vec4 species_colors[MAX_SPECIES];
+};
+
+layout(set = 1, binding = 1)
+uniform ColorSettings {
+    uint color_mode;
+    uint colormap;
+    float color_min;
+    float color_max;
+};
 
-vec3 orthogonal(vec3 v) {
-    float x = abs(v.x);
-    float y = abs(v.y);
-    float z = abs(v.z);
+// Interpolation in boids.rs
+layout(set = 2, binding = 0)
+uniform Interpolation {
+    float alpha;
+    float max_distance;
+};
 
-    vec3 other = x < y ? (x < z ? vec3(1, 0, 0) : vec3(0, 0, 1)) : (y < z ? vec3(0, 1, 0) : vec3(0, 0, 1));
-    return cross(v, other);
+vec3 rotate(vec4 quaternion, vec3 vec) {
+    return vec + 2.0 * cross(quaternion.xyz, cross(quaternion.xyz, vec) + quaternion.w * vec);
 }
 
-vec4 get_rotation_between(vec3 u, vec3 v) {
-    vec4 q;
-    vec3 v0 = normalize(u);
-    vec3 v1 = normalize(v);
+// same as hash in boids.comp
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
 
-    float d = dot(v0, v1);
+// Polynomial fits of the matplotlib colormaps, t in 0..1.
+vec3 viridis(float t) {
+    const vec3 c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    const vec3 c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    const vec3 c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    const vec3 c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    const vec3 c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    const vec3 c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    const vec3 c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
 
-    if (d < (eps - 1.0)) {
-        q = vec4(normalize(orthogonal(u)), 0);
-    } else {
-        float s = sqrt((1+d)*2);
-        float invs = 1/s;
+vec3 magma(float t) {
+    const vec3 c0 = vec3(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933);
+    const vec3 c1 = vec3(0.2516605407371642, 0.6775232436837668, 2.494026599312351);
+    const vec3 c2 = vec3(8.353717279216625, -3.577719514958484, 0.3144679030132573);
+    const vec3 c3 = vec3(-27.66873308576866, 14.26473078096533, -13.64921318813922);
+    const vec3 c4 = vec3(52.17613981234068, -27.94360607168351, 12.94416944238394);
+    const vec3 c5 = vec3(-50.76852536473588, 29.04658282127291, 4.23415299384598);
+    const vec3 c6 = vec3(18.65570506591883, -11.48977351997711, -5.601961508734096);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
 
-        vec3 c = cross(v0, v1);
+vec4 map_color(float t) {
+    t = clamp(t, 0.0, 1.0);
+    vec3 color = colormap == COLORMAP_MAGMA ? magma(t) : viridis(t);
+    return vec4(clamp(color, 0.0, 1.0), 1.0);
+}
 
-        q.x = c.x * invs;
-        q.y = c.y * invs;
-        q.z = c.z * invs;
-        q.w = s * 0.5;
-        q = normalize(q);
-    }
-    return q;
+float normalized(float value) {
+    return (value - color_min) / max(color_max - color_min, 1e-6);
 }
 
-vec3 rotate(vec4 quaternion, vec3 vec) {
-    return vec + 2.0 * cross(quaternion.xyz, cross(quaternion.xyz, vec) + quaternion.w * vec);
+float random_value(uint x) {
+    return float(hash(x) >> 8) / float(1u << 24);
 }
 
+vec4 boid_color() {
+    switch (color_mode) {
+        case COLOR_SPEED:
+            return map_color(normalized(length(a_velocity.xyz)));
+        case COLOR_HEADING:
+            return vec4(0.5 + 0.5 * normalize(a_velocity.xyz), 1.0);
+        case COLOR_NEIGHBOURS:
+            return map_color(normalized(float(a_neighbours)));
+        case COLOR_ID:
+            return map_color(random_value(a_index));
+        case COLOR_CLUSTER:
+            return map_color(random_value(a_cluster));
+        default:
+            return species_colors[a_species];
+    }
+}
 
 void main() {
-    gl_Position = u_view_proj *  (vec4(0.05 * rotate(get_rotation_between(vec3(1,0,0), a_rotation.xyz),a_position.xyz), 1.0)+a_translation);
-}
\ No newline at end of file
+    v_color = boid_color();
+
+    vec3 translation = a_translation.xyz;
+    vec4 rotation = a_rotation;
+    if (distance(a_previous_translation.xyz, a_translation.xyz) <= max_distance) {
+        translation = mix(a_previous_translation.xyz, a_translation.xyz, alpha);
+        // q and -q are the same rotation, blend towards the closer one
+        vec4 previous = a_previous_rotation;
+        if (dot(previous, a_rotation) < 0) {
+            previous = -previous;
+        }
+        rotation = normalize(mix(previous, a_rotation, alpha));
+    }
+
+    // a_rotation is computed in boids.comp, the scale and forward axis are baked into a_position
+    gl_Position = u_view_proj * vec4(rotate(rotation, a_position.xyz) + translation, 1.0);
+    // only used by the point pipeline of the far boids
+    gl_PointSize = 1.0;
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in float v_alpha;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 1, binding = 1) uniform TrailUniforms {
+    uint trail_length;
+    uint trail_head;
+    vec4 trail_color;
+};
+
+void main() {
+    f_color = vec4(trail_color.rgb, v_alpha);
+}
\ No newline at end of file
@@ -0,0 +1,26 @@
+#version 450
+
+layout(location = 0) out float v_alpha;
+
+layout(set=0, binding=0) 
+uniform Uniforms {
+    mat4 u_view_proj;
+};
+
+layout(std430, set = 1, binding = 0) readonly buffer Trails {
+    vec4 trail[];
+};
+
+layout(set = 1, binding = 1) uniform TrailUniforms {
+    uint trail_length;
+    uint trail_head;
+    vec4 trail_color;
+};
+
+void main() {
+    // gl_InstanceIndex is the boid, gl_VertexIndex goes from the oldest to the newest position
+    uint slot = (trail_head + 1 + gl_VertexIndex) % trail_length;
+    vec4 point = trail[gl_InstanceIndex * trail_length + slot];
+    v_alpha = trail_color.a * float(gl_VertexIndex + 1) / float(trail_length);
+    gl_Position = u_view_proj * vec4(point.xyz, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,342 @@
+// Runs the boids without a window and writes trajectories and/or metrics, e.g.
+//
+//     cargo run --release --bin simulate -- --steps 10000 --boids 4096 --interval 10 \
+//         --trajectory out.bin --metrics metrics.csv --param view_radius=3
+//
+// Falls back to the CPU reference (step_cpu) if there is no adapter.
+
+use futures::executor::block_on;
+use learn_wgpu::boids::*;
+use learn_wgpu::bounds::*;
+use learn_wgpu::integrator::Integrator;
+use learn_wgpu::metrics::FlockMetrics;
+use learn_wgpu::point_cloud::PointCloud;
+use learn_wgpu::recorder::*;
+use learn_wgpu::sdf::SdfParams;
+use learn_wgpu::spawn::BoidSpawn;
+use learn_wgpu::species::SpeciesSettings;
+use learn_wgpu::steering::*;
+use learn_wgpu::vector_field::VectorField;
+use std::io::Write;
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: simulate [options]
+    --steps N              number of steps to run (1000)
+    --boids N              number of boids (1024)
+    --seed N               seed of the initial boids (0)
+    --dt SECONDS           length of a step (0.01)
+    --integrator NAME      euler, semi-implicit-euler, verlet or rk2 (semi-implicit-euler)
+    --interval N           steps between trajectory frames and metrics rows (10)
+    --bounds HALF_EXTENT   box the boids are steered back into, 0 or less for none (10)
+    --param NAME=VALUE     overrides a FlockParams field, e.g. view_radius=3
+    --trajectory PATH      writes positions and velocities, CSV for .csv paths, binary otherwise
+    --metrics PATH         writes the FlockMetrics as CSV
+    --cpu                  runs the CPU reference even if there is a GPU";
+
+const METRICS_HEADER: &str = "step,boid_count,mean_speed,polarization,center_x,center_y,center_z,radius_of_gyration,mean_nearest_neighbour_distance";
+
+struct Options {
+    steps: u64,
+    spawn: BoidSpawn,
+    dt: f32,
+    integrator: Integrator,
+    interval: u32,
+    bounds: WorldBounds,
+    params: FlockParams,
+    trajectory: Option<PathBuf>,
+    metrics: Option<PathBuf>,
+    cpu: bool,
+}
+
+fn main() {
+    let options = match parse_options(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = if options.cpu {
+        run_cpu(&options)
+    } else {
+        match block_on(request_device()) {
+            Some((device, queue)) => run_gpu(&options, &device, &queue),
+            None => {
+                eprintln!("no adapter found, running the CPU reference");
+                run_cpu(&options)
+            }
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options, failure::Error> {
+    let mut options = Options {
+        steps: 1000,
+        spawn: BoidSpawn::default(),
+        dt: 0.01,
+        integrator: Integrator::default(),
+        interval: 10,
+        bounds: WorldBounds::default(),
+        params: FlockParams::default(),
+        trajectory: None,
+        metrics: None,
+        cpu: false,
+    };
+    let mut half_extent = 10.0;
+    while let Some(arg) = args.next() {
+        if arg == "--cpu" {
+            options.cpu = true;
+            continue;
+        }
+        let value = match args.next() {
+            Some(value) => value,
+            None => failure::bail!("missing value for {}", arg),
+        };
+        match arg.as_str() {
+            "--steps" => options.steps = value.parse()?,
+            "--boids" => options.spawn.count = value.parse()?,
+            "--seed" => options.spawn.seed = value.parse()?,
+            "--dt" => options.dt = value.parse()?,
+            "--integrator" => options.integrator = value.parse()?,
+            "--interval" => options.interval = value.parse::<u32>()?.max(1),
+            "--bounds" => half_extent = value.parse()?,
+            "--param" => {
+                let mut split = value.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(name), Some(v)) => set_param(&mut options.params, name, v.parse()?)?,
+                    _ => failure::bail!("expected NAME=VALUE, got {}", value),
+                }
+            }
+            "--trajectory" => options.trajectory = Some(value.into()),
+            "--metrics" => options.metrics = Some(value.into()),
+            _ => failure::bail!("unknown option {}", arg),
+        }
+    }
+    if half_extent > 0.0 {
+        options.bounds = WorldBounds::aabb(
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::new(half_extent, half_extent, half_extent),
+            BoundaryMode::Steer,
+        )?;
+    }
+    options.params.validate()?;
+    Ok(options)
+}
+
+fn set_param(params: &mut FlockParams, name: &str, value: f32) -> Result<(), failure::Error> {
+    let param = match name {
+        "max_steer_force" => &mut params.max_steer_force,
+        "max_speed" => &mut params.max_speed,
+        "min_speed" => &mut params.min_speed,
+        "view_radius" => &mut params.view_radius,
+        "avoid_radius" => &mut params.avoid_radius,
+        "align_weight" => &mut params.align_weight,
+        "cohesion_weight" => &mut params.cohesion_weight,
+        "seperate_weight" => &mut params.seperate_weight,
+        "collisions_avoid_dst" => &mut params.collisions_avoid_dst,
+        "avoid_collision_weight" => &mut params.avoid_collision_weight,
+        "collision_avoid_force_dst" => &mut params.collision_avoid_force_dst,
+        "bank_weight" => &mut params.bank_weight,
+        "up_smoothing" => &mut params.up_smoothing,
+        "view_angle" => &mut params.view_angle,
+        "view_falloff" => &mut params.view_falloff,
+        _ => failure::bail!("unknown parameter {}", name),
+    };
+    *param = value;
+    Ok(())
+}
+
+// Without a surface any adapter will do, the secondary backends cover software implementations.
+async fn request_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    for backends in &[wgpu::BackendBit::PRIMARY, wgpu::BackendBit::SECONDARY] {
+        let instance = wgpu::Instance::new(*backends);
+        let adapter = instance
+            .request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                },
+                wgpu::UnsafeExtensions::disallow(),
+            )
+            .await;
+        if let Some(adapter) = adapter {
+            let device = adapter
+                .request_device(
+                    &wgpu::DeviceDescriptor {
+                        extensions: wgpu::Extensions::default(),
+                        limits: Default::default(),
+                        shader_validation: true,
+                    },
+                    None,
+                )
+                .await;
+            if let Ok(device) = device {
+                return Some(device);
+            }
+        }
+    }
+    None
+}
+
+fn create_metrics_file(
+    options: &Options,
+) -> Result<Option<std::io::BufWriter<std::fs::File>>, failure::Error> {
+    match &options.metrics {
+        Some(path) => {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            writeln!(file, "{}", METRICS_HEADER)?;
+            Ok(Some(file))
+        }
+        None => Ok(None),
+    }
+}
+
+fn write_metrics<W: Write>(
+    file: &mut W,
+    step: u64,
+    m: &FlockMetrics,
+) -> Result<(), failure::Error> {
+    writeln!(
+        file,
+        "{},{},{},{},{},{},{},{},{}",
+        step,
+        m.boid_count,
+        m.mean_speed,
+        m.polarization,
+        m.center_of_mass.x,
+        m.center_of_mass.y,
+        m.center_of_mass.z,
+        m.radius_of_gyration,
+        m.mean_nearest_neighbour_distance
+    )?;
+    Ok(())
+}
+
+fn run_gpu(
+    options: &Options,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<(), failure::Error> {
+    // the boid model is never drawn
+    let descriptor = BoidsDescriptor::from_triangles(
+        vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        vec![0, 1, 2],
+    )
+    .with_spawn(options.spawn.clone());
+    let point_cloud = PointCloud::new_sphere(device, 1000);
+    let mut boids = Boids::create_boids(
+        device,
+        &descriptor,
+        &[],
+        &point_cloud.vertex_buffer,
+        point_cloud.num_vertices,
+    )?;
+    boids.set_params(options.params)?;
+    boids.set_bounds(options.bounds);
+    boids.set_integrator(options.integrator);
+    if let Some(path) = &options.trajectory {
+        boids.start_recording(
+            device,
+            path,
+            TrajectoryFormat::from_path(path),
+            options.interval,
+            options.dt,
+        )?;
+    }
+    let mut metrics_file = create_metrics_file(options)?;
+
+    for _ in 0..options.steps {
+        // the metrics pass measures the boids before the update
+        let measure = metrics_file.is_some() && boids.step() % options.interval as u64 == 0;
+        boids.set_metrics_enabled(measure);
+        queue.submit(vec![boids.update(device, options.dt)]);
+        boids.poll_recording(device)?;
+        if let Some(file) = &mut metrics_file {
+            if measure {
+                if let Some(metrics) = boids.wait_metrics(device) {
+                    write_metrics(file, boids.step() - 1, &metrics)?;
+                }
+            }
+        }
+    }
+
+    boids.stop_recording(device)?;
+    if let Some(file) = &mut metrics_file {
+        file.flush()?;
+    }
+    Ok(())
+}
+
+// Same output as run_gpu, without the scene collision avoidance and the spatial grid.
+fn run_cpu(options: &Options) -> Result<(), failure::Error> {
+    let mut boids = options.spawn.generate();
+    let species = SpeciesSettings::default();
+    let steering = Steering::default();
+    let field = VectorField::zero();
+    let mut uniforms = ComputeUniforms {
+        triangle_count: 0,
+        boid_count: boids.len() as u32,
+        sample_cout: 0,
+        delta: options.dt,
+        cell_size: options.params.view_radius,
+        cell_table_size: 0,
+        workgroup_size: 0,
+        species_count: species.count(),
+        params: options.params,
+        goal_count: 0,
+        path_point_count: 0,
+        path_closed: 0,
+        time: 0.0,
+        steering: SteeringParams::default(),
+        bounds: options.bounds,
+        vector_field: field.params(0.0),
+        trail_length: 0,
+        trail_head: 0,
+        trail_reset: 0,
+        integrator: options.integrator as u32,
+        sdf: SdfParams::default(),
+    };
+
+    let mut trajectory = match &options.trajectory {
+        Some(path) => Some(TrajectoryWriter::create(
+            path,
+            TrajectoryFormat::from_path(path),
+            &TrajectoryHeader {
+                boid_count: boids.len() as u32,
+                interval: options.interval,
+                dt: options.dt,
+                params: options.params,
+            },
+        )?),
+        None => None,
+    };
+    let mut metrics_file = create_metrics_file(options)?;
+
+    for step in 0..options.steps {
+        if let Some(file) = &mut metrics_file {
+            if step % options.interval as u64 == 0 {
+                let metrics = FlockMetrics::from_boids(&boids, uniforms.cell_size);
+                write_metrics(file, step, &metrics)?;
+            }
+        }
+        uniforms.time += options.dt;
+        boids = step_cpu(&boids, &uniforms, &species, &steering, &field);
+        if let Some(writer) = &mut trajectory {
+            if (step + 1) % options.interval as u64 == 0 {
+                writer.write_frame(&TrajectoryFrame::from_boids(step + 1, &boids))?;
+            }
+        }
+    }
+
+    if let Some(writer) = &mut trajectory {
+        writer.flush()?;
+    }
+    if let Some(file) = &mut metrics_file {
+        file.flush()?;
+    }
+    Ok(())
+}
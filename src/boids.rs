@@ -1,16 +1,102 @@
-use super::model::Vertex;
+use super::bounds::*;
+use super::bvh::*;
+use super::coloring::*;
+use super::compute::*;
+use super::grid::*;
+use super::integrator::*;
+use super::lod::*;
+use super::metrics::*;
+use super::model::{Mesh, Model, Vertex};
 use super::point_cloud::*;
+use super::recorder::*;
+use super::sdf::*;
+use super::spawn::*;
+use super::species::*;
 use super::state::*;
+use super::steering::*;
+use super::trails::*;
 use super::uniforms::*;
+use super::vector_field::*;
+use cgmath::InnerSpace;
 use include_glsl::include_glsl;
-use rand::Rng;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Defaults for FlockParams, these used to be compile time constants in boids.comp.
+pub const EPS: f32 = 0.005;
+pub const MAX_STEER_FORCE: f32 = 5.0;
+pub const MAX_SPEED: f32 = 5.0;
+pub const MIN_SPEED: f32 = 3.0;
+pub const VIEW_RADIUS: f32 = 2.5;
+pub const AVOID_RADIUS: f32 = 0.4;
+pub const ALIGN_WEIGHT: f32 = 4.0;
+pub const COHESION_WEIGHT: f32 = 3.0;
+pub const SEPERATE_WEIGHT: f32 = 4.0;
+pub const COLLISIONS_AVOID_DST: f32 = 2.5;
+pub const AVOID_COLLISION_WEIGHT: f32 = 10.0;
+pub const COLLISION_AVOID_FORCE_DST: f32 = 0.1 * 0.1;
+pub const BANK_WEIGHT: f32 = 0.1;
+pub const UP_SMOOTHING: f32 = 4.0;
+pub const VIEW_ANGLE: f32 = 2.0 * std::f32::consts::PI;
+pub const VIEW_FALLOFF: f32 = 0.0;
+
+// Cluster labels are only passed on this many neighbours, see MAX_CLUSTER_HOPS in boids.comp.
+pub const MAX_CLUSTER_HOPS: u32 = 32;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct Boid {
-    pos: [f32; 4],
-    vel: [f32; 4],
+    pub pos: [f32; 4],
+    pub vel: [f32; 4],
+    // quaternion rotating the boid model, which points along +x with +y up, written by the compute pass
+    pub rotation: [f32; 4],
+    // smoothed up vector the rotation is built from
+    pub up: [f32; 3],
+    pub species: u32,
+    // number of boids in the perception cone during the last step
+    pub neighbours: u32,
+    // Smallest index of the boids connected to this one through chains of neighbours, propagated
+    // one neighbour per step. u32::MAX until the first step.
+    pub cluster: u32,
+    // number of neighbours the cluster label was passed on
+    pub cluster_hops: u32,
+    // Position in the boid buffer during the last step. The boids are reordered for drawing,
+    // see BoidLod, so this keeps the random colors per boid stable.
+    pub index: u32,
+}
+
+impl Boid {
+    pub fn new(pos: cgmath::Vector3<f32>, vel: cgmath::Vector3<f32>) -> Self {
+        let forward = if vel.magnitude() < EPS {
+            cgmath::Vector3::unit_x()
+        } else {
+            vel.normalize()
+        };
+        let up = orthogonal_up(cgmath::Vector3::unit_y(), forward);
+        Self {
+            pos: [pos.x, pos.y, pos.z, 1.0],
+            vel: [vel.x, vel.y, vel.z, 0.0],
+            rotation: rotation_from_basis(forward, up),
+            up: [up.x, up.y, up.z],
+            species: 0,
+            neighbours: 0,
+            cluster: std::u32::MAX,
+            cluster_hops: 0,
+            index: 0,
+        }
+    }
+
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.pos[0], self.pos[1], self.pos[2])
+    }
+
+    pub fn velocity(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.vel[0], self.vel[1], self.vel[2])
+    }
+
+    pub fn up(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.up[0], self.up[1], self.up[2])
+    }
 }
 
 unsafe impl bytemuck::Pod for Boid {}
@@ -21,268 +107,1289 @@ impl Vertex for Boid {
         wgpu::VertexBufferDescriptor {
             stride: std::mem::size_of::<Boid>() as wgpu::BufferAddress,
             step_mode: wgpu::InputStepMode::Instance,
-            attributes: &wgpu::vertex_attr_array![1 => Float4, 2 => Float4],
+            attributes: &wgpu::vertex_attr_array![1 => Float4, 2 => Float4, 3 => Float4, 4 => Float3, 5 => Uint, 6 => Uint, 7 => Uint, 8 => Uint, 9 => Uint],
+        }
+    }
+}
+
+impl Boid {
+    // Position and rotation of the boids at the previous step, bound next to Boid::desc.
+    fn previous_desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: std::mem::size_of::<Boid>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                // rotation, after pos and vel
+                wgpu::VertexAttributeDescriptor {
+                    offset: 32,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+// Uniform of the boids render pipeline blending between the previous and the current step.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Interpolation {
+    // 0 draws the previous step, 1 the current one
+    pub alpha: f32,
+    // boids that moved further in one step, e.g. by wrapping around the bounds, aren't blended
+    pub max_distance: f32,
+    pub _padding: [f32; 2],
+}
+
+unsafe impl bytemuck::Pod for Interpolation {}
+unsafe impl bytemuck::Zeroable for Interpolation {}
+
+// Where the boid model comes from, see BoidsDescriptor.
+#[derive(Clone, Debug)]
+enum BoidGeometry {
+    Obj(PathBuf),
+    Triangles {
+        positions: Vec<[f32; 3]>,
+        indices: Vec<u32>,
+    },
+}
+
+impl BoidGeometry {
+    fn from_meshes<'a, I: IntoIterator<Item = &'a Mesh>>(meshes: I) -> Self {
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for mesh in meshes {
+            let offset = positions.len() as u32;
+            positions.extend_from_slice(&mesh.positions);
+            indices.extend(mesh.indices.iter().map(|i| offset + i));
+        }
+        BoidGeometry::Triangles { positions, indices }
+    }
+
+    // All models of an OBJ file are merged into one mesh.
+    fn load(&self) -> Result<(Vec<[f32; 3]>, Vec<u32>), failure::Error> {
+        match self {
+            BoidGeometry::Obj(path) => {
+                let (obj_models, _) = tobj::load_obj(path, true)?;
+                let mut positions = Vec::new();
+                let mut indices = Vec::new();
+                for m in obj_models {
+                    let offset = positions.len() as u32;
+                    positions.extend(
+                        m.mesh
+                            .positions
+                            .chunks_exact(3)
+                            .map(|p| [p[0], p[1], p[2]]),
+                    );
+                    indices.extend(m.mesh.indices.iter().map(|i| offset + i));
+                }
+                Ok((positions, indices))
+            }
+            BoidGeometry::Triangles { positions, indices } => {
+                Ok((positions.clone(), indices.clone()))
+            }
+        }
+    }
+}
+
+// Describes the boids created by Boids::create_boids. The boid model is scaled and rotated so
+// its forward axis points along +x, the direction boids.comp orients along the velocity.
+#[derive(Clone, Debug)]
+pub struct BoidsDescriptor {
+    geometry: BoidGeometry,
+    scale: f32,
+    forward: cgmath::Vector3<f32>,
+    spawn: BoidSpawn,
+    species: SpeciesSettings,
+    workgroup_size: WorkgroupSize,
+    sdf_resolution: u32,
+}
+
+impl Default for BoidsDescriptor {
+    fn default() -> Self {
+        Self::from_obj("assets/models/boid.obj")
+    }
+}
+
+impl BoidsDescriptor {
+    pub fn from_obj<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_geometry(BoidGeometry::Obj(path.as_ref().to_path_buf()))
+    }
+
+    // Only the CPU copy of the geometry is used, the mesh's own buffers have a different vertex layout.
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        Self::with_geometry(BoidGeometry::from_meshes(std::iter::once(mesh)))
+    }
+
+    // A triangle list, e.g. for running without any assets.
+    pub fn from_triangles(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Self {
+        Self::with_geometry(BoidGeometry::Triangles { positions, indices })
+    }
+
+    // All meshes of the model are merged, the materials are ignored.
+    pub fn from_model(model: &Model) -> Self {
+        Self::with_geometry(BoidGeometry::from_meshes(&model.meshes))
+    }
+
+    fn with_geometry(geometry: BoidGeometry) -> Self {
+        Self {
+            geometry,
+            scale: 0.05,
+            forward: cgmath::Vector3::unit_x(),
+            spawn: BoidSpawn::default(),
+            species: SpeciesSettings::default(),
+            workgroup_size: WorkgroupSize::default(),
+            sdf_resolution: 32,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    // The axis the nose of the model points along in model space.
+    pub fn with_forward(mut self, forward: cgmath::Vector3<f32>) -> Self {
+        self.forward = forward;
+        self
+    }
+
+    pub fn with_spawn(mut self, spawn: BoidSpawn) -> Self {
+        self.spawn = spawn;
+        self
+    }
+
+    pub fn with_species(mut self, species: SpeciesSettings) -> Self {
+        self.species = species;
+        self
+    }
+
+    pub fn with_workgroup_size(mut self, workgroup_size: WorkgroupSize) -> Self {
+        self.workgroup_size = workgroup_size;
+        self
+    }
+
+    // Cells of the SignedDistanceField along the longest axis of the scene.
+    pub fn with_sdf_resolution(mut self, resolution: u32) -> Self {
+        self.sdf_resolution = resolution;
+        self
+    }
+
+    // Loads the geometry and bakes the scale and forward axis into the vertices.
+    fn mesh(&self) -> Result<(Vec<Point>, Vec<u32>), failure::Error> {
+        if self.scale <= 0.0 {
+            failure::bail!("boid scale {} has to be positive", self.scale);
+        }
+        if self.forward.magnitude() < EPS {
+            failure::bail!("boid forward axis {:?} has no direction", self.forward);
+        }
+        let (positions, indices) = self.geometry.load()?;
+        if indices.is_empty() {
+            failure::bail!("boid model has no triangles");
+        }
+        if let Some(i) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+            failure::bail!(
+                "boid model index {} is out of range for {} vertices",
+                i,
+                positions.len()
+            );
+        }
+        // turning around the up axis if the model points along -x
+        let rotation = cgmath::Quaternion::from_arc(
+            self.forward.normalize(),
+            cgmath::Vector3::unit_x(),
+            Some(cgmath::Vector3::unit_y()),
+        );
+        let vertices = positions
+            .iter()
+            .map(|&p| {
+                let p = self.scale * (rotation * cgmath::Vector3::from(p));
+                Point {
+                    pos: [p.x, p.y, p.z, 1.0],
+                }
+            })
+            .collect();
+        Ok((vertices, indices))
+    }
+}
+
+// Queued by spawn and despawn, applied to the boid buffers on the next update.
+enum BoidChange {
+    Spawn(Vec<Boid>),
+    Despawn(Range<u32>),
+}
+
+const BOID_SIZE: wgpu::BufferAddress = std::mem::size_of::<Boid>() as wgpu::BufferAddress;
+
+pub struct Boids {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+
+    // number of boids currently in the buffers
+    num_instances: u32,
+    // number of boids once the pending changes are applied
+    target_instances: u32,
+    capacity: u32,
+    pending: Vec<BoidChange>,
+    spawn: BoidSpawn,
+
+    boid_buffer1: wgpu::Buffer,
+    boid_buffer2: wgpu::Buffer,
+    boid_buffer_index: bool,
+    boid_bind_group_layout: wgpu::BindGroupLayout,
+    boid_bind_group1: wgpu::BindGroup,
+    boid_bind_group2: wgpu::BindGroup,
+
+    grid: BoidGrid,
+    metrics: FlockMetricsPass,
+    metrics_enabled: bool,
+    trails: BoidTrails,
+    lod: BoidLod,
+    recorder: Option<TrajectoryRecorder>,
+    // number of updates so far
+    step: u64,
+
+    // only referenced through the compute bind group, kept so the buffers live as long as it
+    _bvh_node_buffer: wgpu::Buffer,
+    _bvh_triangle_buffer: wgpu::Buffer,
+    sdf: SignedDistanceField,
+    sdf_texture: wgpu::Texture,
+    // uploaded by the next update
+    sdf_staging_buffer: Option<wgpu::Buffer>,
+    compute_scene_bind_group: wgpu::BindGroup,
+
+    compute_uniforms: ComputeUniforms,
+    compute_uniform_buffer: wgpu::Buffer,
+    compute_uniform_bind_group: wgpu::BindGroup,
+
+    species: SpeciesSettings,
+    species_dirty: bool,
+    species_rule_buffer: wgpu::Buffer,
+    species_color_buffer: wgpu::Buffer,
+
+    color_settings: ColorSettings,
+    color_settings_dirty: bool,
+    color_settings_buffer: wgpu::Buffer,
+    color_bind_group_layout: wgpu::BindGroupLayout,
+    color_bind_group: wgpu::BindGroup,
+
+    interpolation_buffer: wgpu::Buffer,
+    interpolation_bind_group_layout: wgpu::BindGroupLayout,
+    interpolation_bind_group: wgpu::BindGroup,
+
+    steering: Steering,
+    steering_dirty: bool,
+    goal_buffer: wgpu::Buffer,
+    path_buffer: wgpu::Buffer,
+
+    vector_field: VectorField,
+    vector_field_buffer: wgpu::Buffer,
+    vector_field_bind_group_layout: wgpu::BindGroupLayout,
+    vector_field_bind_group: wgpu::BindGroup,
+
+    compute_pipeline: wgpu::ComputePipeline,
+    workgroup_size: WorkgroupSize,
+}
+
+impl Boids {
+    pub fn create_boids(
+        device: &wgpu::Device,
+        descriptor: &BoidsDescriptor,
+        scene: &[Mesh],
+        sample_points: &wgpu::Buffer,
+        sample_count: u32,
+    ) -> Result<Self, failure::Error> {
+        let spawn = &descriptor.spawn;
+        let species = descriptor.species.clone();
+        let workgroup_size = descriptor.workgroup_size;
+        // boids.comp and boids.vert index the species tables with the species of every boid
+        if spawn.species_weights.len() > species.count() as usize {
+            failure::bail!(
+                "spawn has weights for {} species, but only {} are configured",
+                spawn.species_weights.len(),
+                species.count()
+            );
+        }
+
+        let (vertices, indices) = descriptor.mesh()?;
+        let vertex_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+        let index_buffer =
+            device.create_buffer_with_data(bytemuck::cast_slice(&indices), wgpu::BufferUsage::INDEX);
+        let num_indices = indices.len() as u32;
+
+        let mut boids = spawn.generate();
+        let num_instances = boids.len() as u32;
+        // buffers can't be empty
+        let capacity = num_instances.max(1);
+        boids.resize(
+            capacity as usize,
+            Boid::new(cgmath::Vector3::new(0.0, 0.0, 0.0), cgmath::Vector3::unit_x()),
+        );
+        let boid_buffer1 = device
+            .create_buffer_with_data(bytemuck::cast_slice(&boids), Self::boid_buffer_usage());
+        let boid_buffer2 = device
+            .create_buffer_with_data(bytemuck::cast_slice(&boids), Self::boid_buffer_usage());
+
+        let mut boid_bind_group_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::COMPUTE,
+                wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: false,
+                },
+            ),
+            wgpu::BindGroupLayoutEntry::new(
+                1,
+                wgpu::ShaderStage::COMPUTE,
+                wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: false,
+                },
+            ),
+        ];
+        boid_bind_group_layout_entries.extend(BoidGrid::setup_bind_group_layout_entries());
+        boid_bind_group_layout_entries
+            .extend(FlockMetricsPass::setup_bind_group_layout_entries());
+        boid_bind_group_layout_entries.push(BoidTrails::setup_bind_group_layout_entry());
+        let boid_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("boid_bind_group_layout"),
+                bindings: &boid_bind_group_layout_entries,
+            });
+        let compute_scene_bind_group_layout =
+            Self::setup_compute_scene_bind_group_layout(device);
+        let compute_uniform_bind_group_layout =
+            ComputeUniforms::setup_bing_group_layout(device);
+        let vector_field_bind_group_layout = Self::setup_vector_field_bind_group_layout(device);
+        let compute_pipline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[
+                    &boid_bind_group_layout,
+                    &compute_scene_bind_group_layout,
+                    &compute_uniform_bind_group_layout,
+                    &vector_field_bind_group_layout,
+                ],
+            });
+
+        let grid = BoidGrid::new(
+            device,
+            capacity,
+            &compute_pipline_layout,
+            workgroup_size,
+        );
+        let metrics = FlockMetricsPass::new(
+            device,
+            capacity,
+            &compute_pipline_layout,
+            workgroup_size,
+        );
+        // filled with the boid positions by the first update
+        let trails = BoidTrails::new(device, capacity, DEFAULT_TRAIL_LENGTH);
+        let lod = BoidLod::new(
+            device,
+            capacity,
+            num_indices,
+            &boid_buffer1,
+            &boid_buffer2,
+            workgroup_size,
+        );
+
+        let (boid_bind_group1, boid_bind_group2) = Self::create_boid_bind_groups(
+            device,
+            &boid_bind_group_layout,
+            &boid_buffer1,
+            &boid_buffer2,
+            &grid,
+            &metrics,
+            &trails,
+        );
+
+        let bvh = Bvh::from_meshes(scene)?;
+        let (bvh_node_buffer, bvh_triangle_buffer) = bvh.create_buffers(device);
+        // The texture is filled by the first update. Sampling adds the distance to the grid outside of
+        // it, so a larger collisions_avoid_dst set later still sees the obstacles at the right distance.
+        let sdf = SignedDistanceField::bake(&bvh, descriptor.sdf_resolution, COLLISIONS_AVOID_DST);
+        let sdf_texture = sdf.create_texture(device);
+        let sdf_staging_buffer = Some(sdf.create_staging_buffer(device));
+        let sdf_view = sdf_texture.create_default_view();
+        let sdf_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let compute_scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &compute_scene_bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(bvh_node_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(bvh_triangle_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(sample_points.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&sdf_view),
+                },
+                wgpu::Binding {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&sdf_sampler),
+                },
+            ],
+            label: Some("compute_scene_bind_group"),
+        });
+
+        // no force until a field is set
+        let vector_field = VectorField::zero();
+        let vector_field_buffer = vector_field.create_buffer(device);
+        let vector_field_bind_group = Self::create_vector_field_bind_group(
+            device,
+            &vector_field_bind_group_layout,
+            &vector_field_buffer,
+        );
+
+        let compute_uniforms = ComputeUniforms {
+            triangle_count: bvh.triangle_count(),
+            boid_count: num_instances,
+            sample_cout: sample_count,
+            delta: 0.0,
+            cell_size: VIEW_RADIUS,
+            cell_table_size: grid.table_size,
+            workgroup_size: workgroup_size.get(),
+            species_count: species.count(),
+            params: FlockParams::default(),
+            goal_count: 0,
+            path_point_count: 0,
+            path_closed: 0,
+            time: 0.0,
+            steering: SteeringParams::default(),
+            bounds: WorldBounds::default(),
+            vector_field: vector_field.params(0.0),
+            trail_length: DEFAULT_TRAIL_LENGTH,
+            trail_head: 0,
+            trail_reset: 1,
+            integrator: Integrator::default() as u32,
+            sdf: sdf.params(CollisionAvoidance::Raycast),
+        };
+        let compute_uniform_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[compute_uniforms]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let species_rule_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(species.rules()),
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        );
+        let steering = Steering::default();
+        let goal_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&steering.goal_data()),
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        );
+        let path_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&steering.path_data()),
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        );
+        let compute_uniform_bind_group = ComputeUniforms::create_bind_group(
+            device,
+            &compute_uniform_buffer,
+            &species_rule_buffer,
+            &goal_buffer,
+            &path_buffer,
+            Some(&compute_uniform_bind_group_layout),
+        );
+
+        let species_color_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(species.colors()),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let color_settings = ColorSettings::default();
+        let color_settings_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[color_settings]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let color_bind_group_layout = Self::setup_color_bind_group_layout(device);
+        let color_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &color_bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(species_color_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(color_settings_buffer.slice(..)),
+                },
+            ],
+            label: Some("color_bind_group"),
+        });
+
+        // draws the current step until the first call to record_interpolation
+        let interpolation_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[Interpolation {
+                alpha: 1.0,
+                max_distance: 0.0,
+                _padding: [0.0; 2],
+            }]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let interpolation_bind_group_layout = Self::setup_interpolation_bind_group_layout(device);
+        let interpolation_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &interpolation_bind_group_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(interpolation_buffer.slice(..)),
+            }],
+            label: Some("interpolation_bind_group"),
+        });
+
+        let compute_pipeline = create_compute_pipeline(
+            device,
+            &compute_pipline_layout,
+            include_glsl!("../shaders/boids.comp"),
+            Some(workgroup_size),
+        );
+
+        Ok(Self {
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+            num_instances,
+            target_instances: num_instances,
+            capacity,
+            pending: Vec::new(),
+            spawn: spawn.clone(),
+            boid_buffer1,
+            boid_buffer2,
+            boid_buffer_index: false,
+            boid_bind_group_layout,
+            boid_bind_group1,
+            boid_bind_group2,
+            grid,
+            metrics,
+            metrics_enabled: true,
+            trails,
+            lod,
+            recorder: None,
+            step: 0,
+            _bvh_node_buffer: bvh_node_buffer,
+            _bvh_triangle_buffer: bvh_triangle_buffer,
+            sdf,
+            sdf_texture,
+            sdf_staging_buffer,
+            compute_scene_bind_group,
+            compute_uniforms,
+            compute_uniform_buffer,
+            compute_uniform_bind_group,
+            species,
+            species_dirty: false,
+            species_rule_buffer,
+            species_color_buffer,
+            color_settings,
+            color_settings_dirty: false,
+            color_settings_buffer,
+            color_bind_group_layout,
+            color_bind_group,
+            interpolation_buffer,
+            interpolation_bind_group_layout,
+            interpolation_bind_group,
+            steering,
+            steering_dirty: false,
+            goal_buffer,
+            path_buffer,
+            vector_field,
+            vector_field_buffer,
+            vector_field_bind_group_layout,
+            vector_field_bind_group,
+            compute_pipeline,
+            workgroup_size,
+        })
+    }
+
+    fn boid_buffer_usage() -> wgpu::BufferUsage {
+        wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::VERTEX
+            | wgpu::BufferUsage::COPY_SRC
+            | wgpu::BufferUsage::COPY_DST
+    }
+
+    // The first bind group reads from buffer1 and writes to buffer2, the second the other way around.
+    fn create_boid_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        boid_buffer1: &wgpu::Buffer,
+        boid_buffer2: &wgpu::Buffer,
+        grid: &BoidGrid,
+        metrics: &FlockMetricsPass,
+        trails: &BoidTrails,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let mut boid_bindings1 = vec![
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(boid_buffer1.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(boid_buffer2.slice(..)),
+            },
+        ];
+        boid_bindings1.extend(grid.bindings());
+        boid_bindings1.extend(metrics.bindings());
+        boid_bindings1.push(trails.binding());
+        let boid_bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &boid_bindings1,
+            label: Some("boid_bind_group1"),
+        });
+        let mut boid_bindings2 = vec![
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(boid_buffer2.slice(..)),
+            },
+            wgpu::Binding {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(boid_buffer1.slice(..)),
+            },
+        ];
+        boid_bindings2.extend(grid.bindings());
+        boid_bindings2.extend(metrics.bindings());
+        boid_bindings2.push(trails.binding());
+        let boid_bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &boid_bindings2,
+            label: Some("boid_bind_group2"),
+        });
+        (boid_bind_group1, boid_bind_group2)
+    }
+
+    fn setup_compute_scene_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("compute_scene_bind_group_layout"),
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    1,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    2,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    3,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D3,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    4,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::Sampler { comparison: false },
+                ),
+            ],
+        })
+    }
+
+    fn setup_vector_field_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("vector_field_bind_group_layout"),
+            bindings: &[wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::COMPUTE,
+                wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                    readonly: true,
+                },
+            )],
+        })
+    }
+
+    fn create_vector_field_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+            }],
+            label: Some("vector_field_bind_group"),
+        })
+    }
+
+    // The species colors and the ColorSettings, both read by boids.vert.
+    fn setup_color_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let uniform = |binding| {
+            wgpu::BindGroupLayoutEntry::new(
+                binding,
+                wgpu::ShaderStage::VERTEX,
+                wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+            )
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("color_bind_group_layout"),
+            bindings: &[uniform(0), uniform(1)],
+        })
+    }
+
+    // Has to be bound at set 1 of the pipeline drawing the boids.
+    pub fn color_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.color_bind_group_layout
+    }
+
+    fn setup_interpolation_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("interpolation_bind_group_layout"),
+            bindings: &[wgpu::BindGroupLayoutEntry::new(
+                0,
+                wgpu::ShaderStage::VERTEX,
+                wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+            )],
+        })
+    }
+
+    // Has to be bound at set 2 of the pipeline drawing the boids.
+    pub fn interpolation_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.interpolation_bind_group_layout
+    }
+
+    // Blends the drawn boids between the last two steps, `alpha` is usually Clock::alpha.
+    // The steps run on a fixed timestep, so this keeps the motion smooth at any frame rate.
+    pub fn record_interpolation(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        alpha: f32,
+    ) {
+        let uniforms = &self.compute_uniforms;
+        let interpolation = Interpolation {
+            alpha,
+            max_distance: 2.0 * uniforms.params.max_speed * uniforms.delta,
+            _padding: [0.0; 2],
+        };
+        let staging_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[interpolation]),
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.interpolation_buffer,
+            0,
+            std::mem::size_of::<Interpolation>() as wgpu::BufferAddress,
+        );
+    }
+
+    pub fn color_settings(&self) -> ColorSettings {
+        self.color_settings
+    }
+
+    // Uploaded on the next update.
+    pub fn set_color_settings(&mut self, settings: ColorSettings) {
+        self.color_settings = settings;
+        self.color_settings_dirty = true;
+    }
+
+    pub fn species(&self) -> &SpeciesSettings {
+        &self.species
+    }
+
+    // The species count has to stay the same, the species of the boids themselves are set by the BoidSpawn.
+    pub fn set_species(&mut self, species: SpeciesSettings) -> Result<(), failure::Error> {
+        if species.count() != self.species.count() {
+            failure::bail!(
+                "expected {} species, got {}",
+                self.species.count(),
+                species.count()
+            );
+        }
+        self.species = species;
+        self.species_dirty = true;
+        Ok(())
+    }
+
+    pub fn steering(&self) -> &Steering {
+        &self.steering
+    }
+
+    // Goals and path are uploaded on the next update, so they can be changed every frame.
+    pub fn set_steering(&mut self, steering: Steering) {
+        self.compute_uniforms.goal_count = steering.goal_count();
+        self.compute_uniforms.path_point_count = steering.path_point_count();
+        self.compute_uniforms.path_closed = steering.path_closed as u32;
+        self.steering = steering;
+        self.steering_dirty = true;
+    }
+
+    pub fn steering_params(&self) -> SteeringParams {
+        self.compute_uniforms.steering
+    }
+
+    pub fn set_steering_params(&mut self, params: SteeringParams) {
+        self.compute_uniforms.steering = params;
+    }
+
+    // Number of boids in the buffers, spawned and despawned boids are only counted after the next update.
+    pub fn num_instances(&self) -> u32 {
+        self.num_instances
+    }
+
+    // Number of boids after the pending spawns and despawns, the ranges passed to despawn refer to these.
+    pub fn len(&self) -> u32 {
+        self.target_instances
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.target_instances == 0
+    }
+
+    // Appends the boids on the next update, growing the buffers if needed. Fails without spawning
+    // anything if a boid has a species that isn't configured.
+    pub fn spawn(&mut self, boids: &[Boid]) -> Result<(), failure::Error> {
+        if let Some(boid) = boids.iter().find(|b| b.species >= self.species.count()) {
+            failure::bail!(
+                "boid species {} is out of range for {} species",
+                boid.species,
+                self.species.count()
+            );
+        }
+        self.queue_spawn(boids.to_vec());
+        Ok(())
+    }
+
+    fn queue_spawn(&mut self, boids: Vec<Boid>) {
+        if boids.is_empty() {
+            return;
+        }
+        self.target_instances += boids.len() as u32;
+        self.pending.push(BoidChange::Spawn(boids));
+    }
+
+    // Removes the boids on the next update. The boids behind the range are moved down to keep the buffers compact.
+    pub fn despawn(&mut self, range: Range<u32>) {
+        let start = range.start.min(self.target_instances);
+        let end = range.end.min(self.target_instances);
+        if start >= end {
+            return;
+        }
+        self.target_instances -= end - start;
+        self.pending.push(BoidChange::Despawn(start..end));
+    }
+
+    // Shrinking removes the boids at the end, growing spawns the boids the BoidSpawn the flock was
+    // created with generates for the new indices.
+    pub fn resize(&mut self, n: u32) {
+        let len = self.target_instances;
+        if n < len {
+            self.despawn(n..len);
+        } else if n > len {
+            // the species of the spawn were checked by create_boids
+            self.queue_spawn(self.spawn.generate_range(len..n));
+        }
+    }
+
+    fn input_buffer(&self) -> &wgpu::Buffer {
+        if self.boid_buffer_index {
+            &self.boid_buffer2
+        } else {
+            &self.boid_buffer1
+        }
+    }
+
+    fn output_buffer(&self) -> &wgpu::Buffer {
+        if self.boid_buffer_index {
+            &self.boid_buffer1
+        } else {
+            &self.boid_buffer2
+        }
+    }
+
+    fn apply_pending(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        for change in std::mem::take(&mut self.pending) {
+            match change {
+                BoidChange::Spawn(boids) => {
+                    let count = self.num_instances + boids.len() as u32;
+                    if count > self.capacity {
+                        self.grow(device, encoder, count);
+                    }
+                    let staging_buffer = device.create_buffer_with_data(
+                        bytemuck::cast_slice(&boids),
+                        wgpu::BufferUsage::COPY_SRC,
+                    );
+                    // written to both buffers, so the one that is drawn is up to date as well
+                    for buffer in &[&self.boid_buffer1, &self.boid_buffer2] {
+                        encoder.copy_buffer_to_buffer(
+                            &staging_buffer,
+                            0,
+                            buffer,
+                            self.num_instances as wgpu::BufferAddress * BOID_SIZE,
+                            boids.len() as wgpu::BufferAddress * BOID_SIZE,
+                        );
+                    }
+                    self.trails
+                        .record_spawn(device, encoder, self.num_instances, &boids);
+                    self.num_instances = count;
+                }
+                BoidChange::Despawn(range) => {
+                    // Buffers can't be copied onto themselves, so the boids are compacted into
+                    // the output buffer and copied back from there.
+                    let end = range.end.min(self.num_instances);
+                    let start = range.start.min(end);
+                    let count = self.num_instances - (end - start);
+                    let (input, output) = (self.input_buffer(), self.output_buffer());
+                    if start > 0 {
+                        encoder.copy_buffer_to_buffer(
+                            input,
+                            0,
+                            output,
+                            0,
+                            start as wgpu::BufferAddress * BOID_SIZE,
+                        );
+                    }
+                    if end < self.num_instances {
+                        encoder.copy_buffer_to_buffer(
+                            input,
+                            end as wgpu::BufferAddress * BOID_SIZE,
+                            output,
+                            start as wgpu::BufferAddress * BOID_SIZE,
+                            (self.num_instances - end) as wgpu::BufferAddress * BOID_SIZE,
+                        );
+                    }
+                    if count > 0 {
+                        encoder.copy_buffer_to_buffer(
+                            output,
+                            0,
+                            input,
+                            0,
+                            count as wgpu::BufferAddress * BOID_SIZE,
+                        );
+                    }
+                    self.trails
+                        .record_despawn(device, encoder, start, end, self.num_instances);
+                    self.num_instances = count;
+                }
+            }
+        }
+        self.compute_uniforms.boid_count = self.num_instances;
+        self.compute_uniforms.cell_table_size = self.grid.table_size;
+    }
+
+    // Reallocates the boid and grid buffers with room for at least `count` boids.
+    fn grow(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, count: u32) {
+        let capacity = count.next_power_of_two();
+        let size = capacity as wgpu::BufferAddress * BOID_SIZE;
+        let boid_buffer1 = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("boid_buffer1"),
+            size,
+            usage: Self::boid_buffer_usage(),
+            mapped_at_creation: false,
+        });
+        let boid_buffer2 = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("boid_buffer2"),
+            size,
+            usage: Self::boid_buffer_usage(),
+            mapped_at_creation: false,
+        });
+        if self.num_instances > 0 {
+            let live = self.num_instances as wgpu::BufferAddress * BOID_SIZE;
+            encoder.copy_buffer_to_buffer(self.input_buffer(), 0, &boid_buffer1, 0, live);
+            encoder.copy_buffer_to_buffer(self.input_buffer(), 0, &boid_buffer2, 0, live);
+        }
+        self.grid.reserve(device, capacity);
+        self.metrics.reserve(device, capacity);
+        self.trails
+            .reserve(device, encoder, capacity, self.num_instances);
+        self.lod.reserve(device, capacity, &boid_buffer1, &boid_buffer2);
+        let (boid_bind_group1, boid_bind_group2) = Self::create_boid_bind_groups(
+            device,
+            &self.boid_bind_group_layout,
+            &boid_buffer1,
+            &boid_buffer2,
+            &self.grid,
+            &self.metrics,
+            &self.trails,
+        );
+        self.boid_buffer1 = boid_buffer1;
+        self.boid_buffer2 = boid_buffer2;
+        self.boid_buffer_index = false;
+        self.boid_bind_group1 = boid_bind_group1;
+        self.boid_bind_group2 = boid_bind_group2;
+        self.capacity = capacity;
+    }
+
+    // Metrics of the most recent step that finished reading back, they lag a few frames behind.
+    // The readback only progresses if `poll_metrics` is called after submitting the updates.
+    pub fn metrics(&self) -> Option<FlockMetrics> {
+        self.metrics.latest()
+    }
+
+    pub fn poll_metrics(&mut self, device: &wgpu::Device) {
+        self.metrics.poll(device);
+    }
+
+    // Blocks until the metrics of the last submitted update are read back. They describe the
+    // boids before that update, so the state after `step() - 1` steps.
+    pub fn wait_metrics(&mut self, device: &wgpu::Device) -> Option<FlockMetrics> {
+        self.metrics.wait(device);
+        self.metrics.latest()
+    }
+
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Writes the positions and velocities every `interval` steps, starting with the state after the
+    // next multiple of `interval`. `dt` is only stored in the header. A previous recording is finished first.
+    pub fn start_recording<P: AsRef<Path>>(
+        &mut self,
+        device: &wgpu::Device,
+        path: P,
+        format: TrajectoryFormat,
+        interval: u32,
+        dt: f32,
+    ) -> Result<(), failure::Error> {
+        self.stop_recording(device)?;
+        let header = TrajectoryHeader {
+            boid_count: self.target_instances,
+            interval,
+            dt,
+            params: self.compute_uniforms.params,
+        };
+        self.recorder = Some(TrajectoryRecorder::create(path, format, &header)?);
+        Ok(())
+    }
+
+    // Waits for the frames still being read back.
+    pub fn stop_recording(&mut self, device: &wgpu::Device) -> Result<(), failure::Error> {
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.finish(device),
+            None => Ok(()),
         }
     }
-}
 
-pub struct Boids {
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
+    // Has to be called after the command buffers returned by update were submitted.
+    pub fn poll_recording(&mut self, device: &wgpu::Device) -> Result<(), failure::Error> {
+        match &mut self.recorder {
+            Some(recorder) => recorder.poll(device),
+            None => Ok(()),
+        }
+    }
 
-    num_instances: u32,
+    // The metrics pass costs about as much as the flocking itself, as it searches the nearest neighbours.
+    pub fn set_metrics_enabled(&mut self, enabled: bool) {
+        self.metrics_enabled = enabled;
+    }
 
-    boid_buffer1: wgpu::Buffer,
-    boid_buffer2: wgpu::Buffer,
-    boid_buffer_index: bool,
-    boid_bind_group1: wgpu::BindGroup,
-    boid_bind_group2: wgpu::BindGroup,
+    pub fn trails(&self) -> &BoidTrails {
+        &self.trails
+    }
 
-    compute_scene_bind_group: wgpu::BindGroup,
+    pub fn lod_distance(&self) -> f32 {
+        self.lod.distance
+    }
 
-    compute_uniforms: ComputeUniforms,
-    compute_uniform_buffer: wgpu::Buffer,
-    compute_uniform_bind_group: wgpu::BindGroup,
+    // Boids further away from the camera are drawn as points by draw_boids_far.
+    pub fn set_lod_distance(&mut self, distance: f32) {
+        self.lod.distance = distance;
+    }
 
-    compute_shader: wgpu::ShaderModule,
-    compute_pipeline: wgpu::ComputePipeline,
-}
+    // Sorts the latest step into the instances of draw_boids_near and draw_boids_far.
+    // Has to be submitted after the updates of the frame.
+    pub fn update_lod(
+        &self,
+        device: &wgpu::Device,
+        eye: cgmath::Point3<f32>,
+    ) -> wgpu::CommandBuffer {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("lod_encoder"),
+        });
+        self.lod.record(
+            device,
+            &mut encoder,
+            eye,
+            self.num_instances,
+            self.boid_buffer_index,
+        );
+        encoder.finish()
+    }
 
-impl Boids {
-    pub fn create_boids(
+    // Same as setup_default_render_pipeline, but for the far boids of draw_boids_far.
+    pub fn setup_point_render_pipeline(
         device: &wgpu::Device,
-        num_instances: u32,
-        scene_indices: & wgpu::Buffer,
-        scene_vertices: & wgpu::Buffer,
-        scene_index_count: u32,
-        sample_points: & wgpu::Buffer,
-        sample_count: u32,
-    ) -> Self {
-        let (obj_models, _) = tobj::load_obj("assets/models/boid.obj", true).unwrap();
-        assert_eq!(obj_models.len(), 1);
-        for m in obj_models {
-            let mut vertices = Vec::new();
-            let m = m.mesh;
-            for i in 0..m.positions.len() / 3 {
-                vertices.push(Point {
-                    pos: [
-                        m.positions[i * 3],
-                        m.positions[i * 3 + 1],
-                        m.positions[i * 3 + 2],
-                        1.0,
-                    ],
-                });
-            }
-            let vertex_buffer = device.create_buffer_with_data(
-                bytemuck::cast_slice(&vertices),
-                wgpu::BufferUsage::VERTEX,
-            );
-            let index_buffer = device.create_buffer_with_data(
-                bytemuck::cast_slice(&m.indices),
-                wgpu::BufferUsage::INDEX,
-            );
-            let num_indices = m.indices.len() as u32;
+        layouts: &[&wgpu::BindGroupLayout],
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let (vs, fs) = Self::setup_shader(device);
+        Self::create_render_pipeline(
+            device,
+            layouts,
+            format,
+            (&vs, fs.as_ref()),
+            wgpu::PrimitiveTopology::PointList,
+            Self::setup_vertex_input().as_ref(),
+        )
+    }
 
-            let mut rng = rand::thread_rng();
+    pub fn set_trail_color(&mut self, color: [f32; 4]) {
+        self.trails.set_color(color);
+    }
 
-            let boids: Vec<_> = std::iter::repeat_with(|| Boid {
-                pos: [
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    1.0,
-                ],
-                vel: [
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    (rng.gen::<f32>() - 0.5) * 5.0,
-                    0.0,
-                ],
-            })
-            .take(num_instances as usize)
-            .collect();
-            let boid_buffer1 = device
-                .create_buffer_with_data(bytemuck::cast_slice(&boids), wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX);
-            let boid_buffer2 = device
-                .create_buffer_with_data(bytemuck::cast_slice(&boids), wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX);
-
-            let boid_bind_group_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some("boid_bind_group_layout"),
-                    bindings: &[
-                        wgpu::BindGroupLayoutEntry::new(
-                            0,
-                            wgpu::ShaderStage::COMPUTE,
-                            wgpu::BindingType::StorageBuffer {
-                                dynamic: false,
-                                min_binding_size: None,
-                                readonly: false,
-                            },
-                        ),
-                        wgpu::BindGroupLayoutEntry::new(
-                            1,
-                            wgpu::ShaderStage::COMPUTE,
-                            wgpu::BindingType::StorageBuffer {
-                                dynamic: false,
-                                min_binding_size: None,
-                                readonly: false,
-                            },
-                        ),
-                    ],
-                });
-            let boid_bind_group1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &boid_bind_group_layout,
-                bindings: &[
-                    wgpu::Binding {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(boid_buffer1.slice(..)),
-                    },
-                    wgpu::Binding {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Buffer(boid_buffer2.slice(..)),
-                    },
-                ],
-                label: Some("boid_bind_group1"),
-            });
-            let boid_bind_group2 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &boid_bind_group_layout,
-                bindings: &[
-                    wgpu::Binding {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(boid_buffer2.slice(..)),
-                    },
-                    wgpu::Binding {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Buffer(boid_buffer1.slice(..)),
-                    },
-                ],
-                label: Some("boid_bind_group2"),
-            });
+    // Restarts all trails with the new length, 0 turns them off.
+    pub fn set_trail_length(&mut self, device: &wgpu::Device, length: u32) {
+        self.trails.set_length(device, self.capacity, length);
+        self.compute_uniforms.trail_length = length;
+        self.compute_uniforms.trail_reset = 1;
+        let (boid_bind_group1, boid_bind_group2) = Self::create_boid_bind_groups(
+            device,
+            &self.boid_bind_group_layout,
+            &self.boid_buffer1,
+            &self.boid_buffer2,
+            &self.grid,
+            &self.metrics,
+            &self.trails,
+        );
+        self.boid_bind_group1 = boid_bind_group1;
+        self.boid_bind_group2 = boid_bind_group2;
+    }
 
-            let compute_scene_bind_group_layout =
-                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: Some("compute_scene_bind_group_layout"),
-                    bindings: &[
-                        wgpu::BindGroupLayoutEntry::new(
-                            0,
-                            wgpu::ShaderStage::COMPUTE,
-                            wgpu::BindingType::StorageBuffer {
-                                dynamic: false,
-                                min_binding_size: None,
-                                readonly: false,
-                            },
-                        ),
-                        wgpu::BindGroupLayoutEntry::new(
-                            1,
-                            wgpu::ShaderStage::COMPUTE,
-                            wgpu::BindingType::StorageBuffer {
-                                dynamic: false,
-                                min_binding_size: None,
-                                readonly: false,
-                            },
-                        ),
-                        wgpu::BindGroupLayoutEntry::new(
-                            2,
-                            wgpu::ShaderStage::COMPUTE,
-                            wgpu::BindingType::StorageBuffer {
-                                dynamic: false,
-                                min_binding_size: None,
-                                readonly: false,
-                            },
-                        ),
-                    ],
-                });
-            let compute_scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &compute_scene_bind_group_layout,
-                bindings: &[
-                    wgpu::Binding {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(scene_indices.slice(..)),
-                    },
-                    wgpu::Binding {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Buffer(scene_vertices.slice(..)),
-                    },
-                    wgpu::Binding {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Buffer(sample_points.slice(..)),
-                    },
-                ],
-                label: Some("compute_scene_bind_group"),
-            });
+    pub fn bounds(&self) -> WorldBounds {
+        self.compute_uniforms.bounds
+    }
 
-            let compute_uniforms = ComputeUniforms {
-                triangle_count: scene_index_count / 3,
-                boid_count: num_instances,
-                sample_cout: sample_count,
-                delta: 0.0,
-            };
-            let compute_uniform_buffer = device.create_buffer_with_data(
-                bytemuck::cast_slice(&[compute_uniforms]),
-                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-            );
-            let compute_uniform_bind_group_layout =
-                ComputeUniforms::setup_bing_group_layout(device);
-            let compute_uniform_bind_group = ComputeUniforms::create_bind_group(
-                device,
-                &compute_uniform_buffer,
-                Some(&compute_uniform_bind_group_layout),
-            );
+    pub fn set_bounds(&mut self, bounds: WorldBounds) {
+        self.compute_uniforms.bounds = bounds;
+    }
 
-            let compute_pipline_layout =
-                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    bind_group_layouts: &[
-                        &boid_bind_group_layout,
-                        &compute_scene_bind_group_layout,
-                        &compute_uniform_bind_group_layout,
-                    ],
-                });
-
-            let compute_shader =
-                Self::create_shader_module(device, include_glsl!("../shaders/boids.comp"));
-
-            let compute_pipeline =
-                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    layout: &compute_pipline_layout,
-                    compute_stage: wgpu::ProgrammableStageDescriptor {
-                        module: &compute_shader,
-                        entry_point: "main",
-                    },
-                });
-
-            return Self {
-                vertex_buffer,
-                index_buffer,
-                num_indices,
-                num_instances,
-                boid_buffer1,
-                boid_buffer2,
-                boid_buffer_index: false,
-                boid_bind_group1,
-                boid_bind_group2,
-                compute_scene_bind_group,
-                compute_uniforms,
-                compute_uniform_buffer,
-                compute_uniform_bind_group,
-                compute_shader,
-                compute_pipeline,
-            };
+    pub fn sdf(&self) -> &SignedDistanceField {
+        &self.sdf
+    }
+
+    pub fn collision_avoidance(&self) -> CollisionAvoidance {
+        if self.compute_uniforms.sdf.avoidance == CollisionAvoidance::Sdf as u32 {
+            CollisionAvoidance::Sdf
+        } else {
+            CollisionAvoidance::Raycast
         }
+    }
+
+    pub fn set_collision_avoidance(&mut self, avoidance: CollisionAvoidance) {
+        self.compute_uniforms.sdf.avoidance = avoidance as u32;
+    }
+
+    pub fn integrator(&self) -> Integrator {
+        Integrator::from_u32(self.compute_uniforms.integrator).unwrap_or_default()
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.compute_uniforms.integrator = integrator as u32;
+    }
+
+    pub fn vector_field(&self) -> &VectorField {
+        &self.vector_field
+    }
+
+    // Replaces the field right away, the buffer is reallocated as the resolution can change.
+    // `strength` scales the sampled vectors into an acceleration.
+    pub fn set_vector_field(&mut self, device: &wgpu::Device, field: VectorField, strength: f32) {
+        self.vector_field_buffer = field.create_buffer(device);
+        self.vector_field_bind_group = Self::create_vector_field_bind_group(
+            device,
+            &self.vector_field_bind_group_layout,
+            &self.vector_field_buffer,
+        );
+        self.compute_uniforms.vector_field = field.params(strength);
+        self.vector_field = field;
+    }
+
+    pub fn set_vector_field_strength(&mut self, strength: f32) {
+        self.compute_uniforms.vector_field.strength = strength;
+    }
+
+    pub fn params(&self) -> FlockParams {
+        self.compute_uniforms.params
+    }
 
-        panic!("no model found")
+    // The parameters are uploaded together with the rest of the compute uniforms on the next update.
+    // Invalid parameters are rejected and the previous ones are kept.
+    pub fn set_params(&mut self, params: FlockParams) -> Result<(), failure::Error> {
+        params.validate()?;
+        self.compute_uniforms.params = params;
+        // The grid cells have to be at least as large as the view radius for the 3x3x3 neighbourhood to cover it.
+        self.compute_uniforms.cell_size = params.view_radius;
+        Ok(())
     }
 
     pub fn update(& mut self, device: &wgpu::Device, delta: f32) -> wgpu::CommandBuffer {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("compute_encoder"),
+        });
+        self.apply_pending(device, &mut encoder);
+        if let Some(staging_buffer) = self.sdf_staging_buffer.take() {
+            self.sdf.record_upload(&mut encoder, &staging_buffer, &self.sdf_texture);
+        }
+
         self.compute_uniforms.delta = delta;
+        self.compute_uniforms.time += delta;
+        self.compute_uniforms.trail_head = self.trails.advance();
         let staging_buffer = device.create_buffer_with_data(
             bytemuck::cast_slice(&[self.compute_uniforms]),
             wgpu::BufferUsage::COPY_SRC,
         );
-
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("compute_encoder"),
-        });
+        self.compute_uniforms.trail_reset = 0;
+        self.trails.record_uniforms(device, &mut encoder);
 
         encoder.copy_buffer_to_buffer(
             &staging_buffer,
@@ -291,15 +1398,94 @@ impl Boids {
             0,
             std::mem::size_of::<ComputeUniforms>() as wgpu::BufferAddress,
         );
+        if self.species_dirty {
+            let rules = self.species.rules();
+            let staging_buffer = device
+                .create_buffer_with_data(bytemuck::cast_slice(rules), wgpu::BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                &self.species_rule_buffer,
+                0,
+                (rules.len() * std::mem::size_of::<SpeciesRule>()) as wgpu::BufferAddress,
+            );
+            let colors = self.species.colors();
+            let staging_buffer = device
+                .create_buffer_with_data(bytemuck::cast_slice(colors), wgpu::BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                &self.species_color_buffer,
+                0,
+                (colors.len() * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+            );
+            self.species_dirty = false;
+        }
+        if self.color_settings_dirty {
+            let staging_buffer = device.create_buffer_with_data(
+                bytemuck::cast_slice(&[self.color_settings]),
+                wgpu::BufferUsage::COPY_SRC,
+            );
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                &self.color_settings_buffer,
+                0,
+                std::mem::size_of::<ColorSettings>() as wgpu::BufferAddress,
+            );
+            self.color_settings_dirty = false;
+        }
+        if self.steering_dirty {
+            let goals = self.steering.goal_data();
+            let staging_buffer = device
+                .create_buffer_with_data(bytemuck::cast_slice(&goals), wgpu::BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                &self.goal_buffer,
+                0,
+                (goals.len() * std::mem::size_of::<Goal>()) as wgpu::BufferAddress,
+            );
+            let path = self.steering.path_data();
+            let staging_buffer = device
+                .create_buffer_with_data(bytemuck::cast_slice(&path), wgpu::BufferUsage::COPY_SRC);
+            encoder.copy_buffer_to_buffer(
+                &staging_buffer,
+                0,
+                &self.path_buffer,
+                0,
+                (path.len() * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+            );
+            self.steering_dirty = false;
+        }
         {
             let mut compute_pass = encoder.begin_compute_pass();
-            compute_pass.set_pipeline(&self.compute_pipeline);
             compute_pass.set_bind_group(0, if self.boid_buffer_index {&self.boid_bind_group2} else {&self.boid_bind_group1}, &[]);
-            self.boid_buffer_index = !self.boid_buffer_index;
             compute_pass.set_bind_group(1, &self.compute_scene_bind_group, &[]);
             compute_pass.set_bind_group(2, &self.compute_uniform_bind_group, &[]);
-            compute_pass.dispatch(self.num_instances, 1, 1);
+            compute_pass.set_bind_group(3, &self.vector_field_bind_group, &[]);
+            self.grid.record(&mut compute_pass, self.num_instances);
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            let (x, y, z) = dispatch_size(self.num_instances, self.workgroup_size);
+            compute_pass.dispatch(x, y, z);
+            // measures the input boids, the grid was built for them
+            if self.metrics_enabled {
+                self.metrics.record(&mut compute_pass, self.num_instances);
+            }
+        }
+        if self.metrics_enabled {
+            self.metrics.record_readback(&mut encoder);
         }
+        self.step += 1;
+        if let Some(recorder) = &mut self.recorder {
+            let output = if self.boid_buffer_index {
+                &self.boid_buffer1
+            } else {
+                &self.boid_buffer2
+            };
+            recorder.record(device, &mut encoder, output, self.num_instances, self.step);
+        }
+        self.boid_buffer_index = !self.boid_buffer_index;
 
         encoder.finish()
     }
@@ -316,10 +1502,14 @@ impl Renderable for Boids {
         )
     }
     fn setup_bind_group_layouts(device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
-        vec![Uniforms::setup_bing_group_layout(device)]
+        vec![
+            Uniforms::setup_bing_group_layout(device),
+            Self::setup_color_bind_group_layout(device),
+            Self::setup_interpolation_bind_group_layout(device),
+        ]
     }
     fn setup_vertex_input<'a>() -> Vec<wgpu::VertexBufferDescriptor<'a>> {
-        vec![Point::desc(), Boid::desc()]
+        vec![Point::desc(), Boid::desc(), Boid::previous_desc()]
     }
     fn setup_default_render_pipeline(
         device: &wgpu::Device,
@@ -369,6 +1559,8 @@ where
         instances: Range<u32>,
         uniforms: &'b wgpu::BindGroup,
     );
+    fn draw_boids_near(&mut self, boids: &'b Boids, uniforms: &'b wgpu::BindGroup);
+    fn draw_boids_far(&mut self, boids: &'b Boids, uniforms: &'b wgpu::BindGroup);
 }
 
 impl<'a, 'b> DrawBoids<'a, 'b> for wgpu::RenderPass<'a>
@@ -386,51 +1578,658 @@ where
     ) {
         self.set_index_buffer(boids.index_buffer.slice(..));
         self.set_vertex_buffer(0, boids.vertex_buffer.slice(..));
-        self.set_vertex_buffer(1, if boids.boid_buffer_index {boids.boid_buffer1.slice(..)} else {boids.boid_buffer2.slice(..)});
+        // the input of the next step is the latest one, the output still holds the one before
+        self.set_vertex_buffer(1, boids.input_buffer().slice(..));
+        self.set_vertex_buffer(2, boids.output_buffer().slice(..));
         self.set_bind_group(0, &uniforms, &[]);
+        self.set_bind_group(1, &boids.color_bind_group, &[]);
+        self.set_bind_group(2, &boids.interpolation_bind_group, &[]);
         self.draw_indexed(0..boids.num_indices, 0, instances);
     }
+    // The boids closer than the LOD distance with the boid mesh, as classified by the last update_lod.
+    fn draw_boids_near(&mut self, boids: &'b Boids, uniforms: &'b wgpu::BindGroup) {
+        let (latest, previous) = boids.lod.near_instances();
+        self.set_index_buffer(boids.index_buffer.slice(..));
+        self.set_vertex_buffer(0, boids.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, latest.slice(..));
+        self.set_vertex_buffer(2, previous.slice(..));
+        self.set_bind_group(0, &uniforms, &[]);
+        self.set_bind_group(1, &boids.color_bind_group, &[]);
+        self.set_bind_group(2, &boids.interpolation_bind_group, &[]);
+        self.draw_indexed_indirect(boids.lod.draw_args_buffer(), MESH_DRAW_ARGS_OFFSET);
+    }
+    // The remaining boids as single points, expects the pipeline of setup_point_render_pipeline.
+    fn draw_boids_far(&mut self, boids: &'b Boids, uniforms: &'b wgpu::BindGroup) {
+        let (latest, previous) = boids.lod.far_instances();
+        self.set_vertex_buffer(0, boids.lod.point_vertex_buffer().slice(..));
+        self.set_vertex_buffer(1, latest.slice(..));
+        self.set_vertex_buffer(2, previous.slice(..));
+        self.set_bind_group(0, &uniforms, &[]);
+        self.set_bind_group(1, &boids.color_bind_group, &[]);
+        self.set_bind_group(2, &boids.interpolation_bind_group, &[]);
+        self.draw_indirect(boids.lod.draw_args_buffer(), POINT_DRAW_ARGS_OFFSET);
+    }
+}
+
+// CPU version of boids.comp. It performs exactly one step of the flocking update without the
+// scene collision avoidance, so flocking behaviour can be tested and debugged without a GPU.
+pub fn step_cpu(
+    boids: &[Boid],
+    uniforms: &ComputeUniforms,
+    species: &SpeciesSettings,
+    steering: &Steering,
+    field: &VectorField,
+) -> Vec<Boid> {
+    let params = &uniforms.params;
+    let delta = uniforms.delta;
+    let integrator = Integrator::from_u32(uniforms.integrator).unwrap_or_default();
+    let count = (uniforms.boid_count as usize).min(boids.len());
+    let boids = &boids[..count];
+    (0..count)
+        .map(|gid| {
+            let pos = boids[gid].position();
+            let mut vel = boids[gid].velocity();
+            let acceleration_at = |pos, vel| {
+                acceleration_at(boids, gid, pos, vel, uniforms, species, steering, field)
+            };
+
+            let (acceleration, neighbours, cluster) = acceleration_at(pos, vel);
+            let new_pos = match integrator {
+                Integrator::Euler => {
+                    let new_pos = pos + vel * delta;
+                    vel += delta * acceleration;
+                    new_pos
+                }
+                Integrator::SemiImplicitEuler => {
+                    vel += delta * acceleration;
+                    pos
+                }
+                Integrator::Verlet => {
+                    let new_pos = pos + vel * delta + 0.5 * acceleration * delta * delta;
+                    let end_vel = limit_speed(vel + delta * acceleration, params);
+                    let (end_acceleration, _, _) = acceleration_at(new_pos, end_vel);
+                    vel += 0.5 * delta * (acceleration + end_acceleration);
+                    new_pos
+                }
+                Integrator::Rk2 => {
+                    let mid_pos = pos + 0.5 * delta * vel;
+                    let mid_vel = limit_speed(vel + 0.5 * delta * acceleration, params);
+                    let (mid_acceleration, _, _) = acceleration_at(mid_pos, mid_vel);
+                    vel += delta * mid_acceleration;
+                    pos + mid_vel * delta
+                }
+            };
+
+            let vel = limit_speed(vel, params);
+            let new_pos = if integrator == Integrator::SemiImplicitEuler {
+                pos + vel * delta
+            } else {
+                new_pos
+            };
+
+            let (pos, vel) = uniforms.bounds.constrain(new_pos, vel);
+            let (rotation, up) = orient(&boids[gid], vel, delta, params);
+            Boid {
+                rotation,
+                up: [up.x, up.y, up.z],
+                species: boids[gid].species,
+                neighbours,
+                cluster: cluster.0,
+                cluster_hops: cluster.1,
+                index: gid as u32,
+                ..Boid::new(pos, vel)
+            }
+        })
+        .collect()
+}
+
+// Same as acceleration_at in boids.comp. Also returns the number of neighbours in the perception
+// cone and the propagated cluster label with its hops.
+#[allow(clippy::too_many_arguments)]
+fn acceleration_at(
+    boids: &[Boid],
+    gid: usize,
+    pos: cgmath::Vector3<f32>,
+    vel: cgmath::Vector3<f32>,
+    uniforms: &ComputeUniforms,
+    species: &SpeciesSettings,
+    steering: &Steering,
+    field: &VectorField,
+) -> (cgmath::Vector3<f32>, u32, (u32, u32)) {
+    let params = &uniforms.params;
+    let mut acceleration = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    let mut neighbours = 0;
+    // (label, hops), compared in this order
+    let mut cluster = (gid as u32, 0);
+    {
+        let mut center = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        let mut alignment = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        let mut mate_weight = 0.0;
+        let mut separate = cgmath::Vector3::new(0.0, 0.0, 0.0);
+
+        let heading = vel.normalize();
+        let cos_half_view = (0.5 * params.view_angle).cos();
+
+        for (i, boid) in boids.iter().enumerate() {
+            if i == gid {
+                continue;
+            }
+
+            let rule = species.rule(boids[gid].species, boid.species);
+            let offset = boid.position() - pos;
+            let dist2 = offset.dot(offset);
+            let in_view = dist2 < params.view_radius * params.view_radius;
+            if in_view
+                && boid.cluster_hops + 1 < MAX_CLUSTER_HOPS
+                && (boid.cluster, boid.cluster_hops + 1) < cluster
+            {
+                cluster = (boid.cluster, boid.cluster_hops + 1);
+            }
+            if offset.dot(heading) < cos_half_view * dist2.sqrt() {
+                continue;
+            }
+            if in_view {
+                let weight = 1.0 - params.view_falloff * dist2.sqrt() / params.view_radius;
+                center += weight * rule.attraction * offset;
+                alignment += weight * rule.alignment * boid.velocity();
+                mate_weight += weight;
+                neighbours += 1;
+            }
+            if dist2 < params.avoid_radius * params.avoid_radius {
+                separate -= rule.avoidance * offset / dist2;
+            }
+        }
+
+        if mate_weight > 0.0 {
+            center /= mate_weight;
+        }
+
+        acceleration += params.cohesion_weight * steer_towards(center, vel, params);
+        acceleration += params.align_weight * steer_towards(alignment, vel, params);
+        acceleration += params.seperate_weight * steer_towards(separate, vel, params);
+    }
+
+    acceleration += goal_force(&steering.goals, pos, vel, boids[gid].species, params);
+    acceleration += uniforms.steering.path_weight * path_force(steering, pos, vel, uniforms);
+    if uniforms.steering.wander_weight > 0.0 {
+        let noise = wander_noise(gid as u32, uniforms.time, uniforms.steering.wander_rate);
+        let target = vel.normalize() + uniforms.steering.wander_jitter * noise;
+        acceleration += uniforms.steering.wander_weight * steer_towards(target, vel, params);
+    }
+    if uniforms.vector_field.strength != 0.0 {
+        acceleration += uniforms.vector_field.strength * field.sample(pos);
+    }
+    let bounds = &uniforms.bounds;
+    if bounds.mode == BoundaryMode::Steer as u32 {
+        let inward = bounds.inward(pos);
+        acceleration +=
+            bounds.weight * inward.magnitude().min(1.0) * steer_towards(inward, vel, params);
+    }
+    (acceleration, neighbours, cluster)
+}
+
+fn limit_speed(vel: cgmath::Vector3<f32>, params: &FlockParams) -> cgmath::Vector3<f32> {
+    let speed = vel.magnitude();
+    clamp(speed, params.min_speed, params.max_speed) * vel / speed
+}
+
+fn steer_towards(
+    vec: cgmath::Vector3<f32>,
+    vel: cgmath::Vector3<f32>,
+    params: &FlockParams,
+) -> cgmath::Vector3<f32> {
+    let l = vec.magnitude();
+    if l.abs() < EPS {
+        return cgmath::Vector3::new(0.0, 0.0, 0.0);
+    }
+    let v = vec / l * params.max_speed - vel;
+    let l = v.magnitude();
+    if l.abs() < EPS {
+        return cgmath::Vector3::new(0.0, 0.0, 0.0);
+    }
+    clamp(l, 0.0, params.max_steer_force) * v / l
+}
+
+fn arrive(
+    offset: cgmath::Vector3<f32>,
+    radius: f32,
+    vel: cgmath::Vector3<f32>,
+    params: &FlockParams,
+) -> cgmath::Vector3<f32> {
+    let dist = offset.magnitude();
+    if dist < EPS {
+        return cgmath::Vector3::new(0.0, 0.0, 0.0);
+    }
+    let speed = if radius > 0.0 {
+        params.max_speed * (dist / radius).min(1.0)
+    } else {
+        params.max_speed
+    };
+    let v = offset / dist * speed - vel;
+    let l = v.magnitude();
+    if l < EPS {
+        return cgmath::Vector3::new(0.0, 0.0, 0.0);
+    }
+    l.min(params.max_steer_force) * v / l
+}
+
+fn goal_force(
+    goals: &[Goal],
+    pos: cgmath::Vector3<f32>,
+    vel: cgmath::Vector3<f32>,
+    species: u32,
+    params: &FlockParams,
+) -> cgmath::Vector3<f32> {
+    let mut force = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    for goal in goals.iter().take(MAX_GOALS) {
+        if goal.species_mask & (1 << species) == 0 {
+            continue;
+        }
+
+        let offset = goal.position() - pos;
+        let radius = goal.radius();
+        let in_range = radius <= 0.0 || offset.dot(offset) < radius * radius;
+        if goal.kind == GoalKind::Seek as u32 && in_range {
+            force += goal.weight * steer_towards(offset, vel, params);
+        } else if goal.kind == GoalKind::Arrive as u32 {
+            force += goal.weight * arrive(offset, radius, vel, params);
+        } else if goal.kind == GoalKind::Flee as u32 && in_range {
+            force += goal.weight * steer_towards(-offset, vel, params);
+        }
+    }
+    force
+}
+
+fn path_force(
+    steering: &Steering,
+    pos: cgmath::Vector3<f32>,
+    vel: cgmath::Vector3<f32>,
+    uniforms: &ComputeUniforms,
+) -> cgmath::Vector3<f32> {
+    let future = pos + vel * uniforms.steering.path_lookahead;
+    match closest_on_path(steering, future) {
+        Some((p, dir)) if (future - p).magnitude2() > uniforms.steering.path_radius.powi(2) => {
+            let target = p + dir * uniforms.steering.path_radius;
+            steer_towards(target - pos, vel, &uniforms.params)
+        }
+        _ => cgmath::Vector3::new(0.0, 0.0, 0.0),
+    }
+}
+
+// Banks the boid into turns by tilting the up vector towards the lateral acceleration,
+// which is then smoothed over time so the boid doesn't snap around when the velocity flips.
+fn orient(
+    boid: &Boid,
+    vel: cgmath::Vector3<f32>,
+    delta: f32,
+    params: &FlockParams,
+) -> ([f32; 4], cgmath::Vector3<f32>) {
+    let forward = vel.normalize();
+    let turn = if delta > 0.0 {
+        (vel - boid.velocity()) / delta
+    } else {
+        cgmath::Vector3::new(0.0, 0.0, 0.0)
+    };
+    let lateral = turn - turn.dot(forward) * forward;
+    let mut target_up = cgmath::Vector3::unit_y() + params.bank_weight * lateral;
+    if target_up.magnitude() < EPS {
+        target_up = cgmath::Vector3::unit_y();
+    }
+    let t = 1.0 - (-params.up_smoothing * delta).exp();
+    let up = boid.up() + (target_up.normalize() - boid.up()) * t;
+    let up = orthogonal_up(up, forward);
+    (rotation_from_basis(forward, up), up)
+}
+
+// Removes the forward component from `up`, falling back to any orthogonal vector if they are parallel.
+fn orthogonal_up(up: cgmath::Vector3<f32>, forward: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let up = up - up.dot(forward) * forward;
+    if up.magnitude() < EPS {
+        orthogonal(forward).normalize()
+    } else {
+        up.normalize()
+    }
+}
+
+fn orthogonal(v: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let (x, y, z) = (v.x.abs(), v.y.abs(), v.z.abs());
+    let other = if x < y {
+        if x < z {
+            cgmath::Vector3::unit_x()
+        } else {
+            cgmath::Vector3::unit_z()
+        }
+    } else if y < z {
+        cgmath::Vector3::unit_y()
+    } else {
+        cgmath::Vector3::unit_z()
+    };
+    v.cross(other)
+}
+
+// Quaternion (xyz, w) of the rotation mapping +x to `forward` and +y to `up`, which have to be orthonormal.
+pub fn rotation_from_basis(forward: cgmath::Vector3<f32>, up: cgmath::Vector3<f32>) -> [f32; 4] {
+    let side = forward.cross(up);
+    // m[row][column] of the matrix with the columns forward, up and side
+    let m = [
+        [forward.x, up.x, side.x],
+        [forward.y, up.y, side.y],
+        [forward.z, up.z, side.z],
+    ];
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [
+            (m[2][1] - m[1][2]) / s,
+            (m[0][2] - m[2][0]) / s,
+            (m[1][0] - m[0][1]) / s,
+            0.25 * s,
+        ]
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+        [
+            0.25 * s,
+            (m[0][1] + m[1][0]) / s,
+            (m[0][2] + m[2][0]) / s,
+            (m[2][1] - m[1][2]) / s,
+        ]
+    } else if m[1][1] > m[2][2] {
+        let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+        [
+            (m[0][1] + m[1][0]) / s,
+            0.25 * s,
+            (m[1][2] + m[2][1]) / s,
+            (m[0][2] - m[2][0]) / s,
+        ]
+    } else {
+        let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+        [
+            (m[0][2] + m[2][0]) / s,
+            (m[1][2] + m[2][1]) / s,
+            0.25 * s,
+            (m[1][0] - m[0][1]) / s,
+        ]
+    }
+}
+
+// GLSL clamp, which unlike f32::clamp does not panic if min > max.
+fn clamp(x: f32, min: f32, max: f32) -> f32 {
+    x.max(min).min(max)
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct ComputeUniforms {
-    triangle_count: u32,
-    boid_count: u32,
-    sample_cout: u32,
-    delta: f32,
+    pub triangle_count: u32,
+    pub boid_count: u32,
+    pub sample_cout: u32,
+    pub delta: f32,
+    pub cell_size: f32,
+    pub cell_table_size: u32,
+    pub workgroup_size: u32,
+    pub species_count: u32,
+    pub params: FlockParams,
+    pub goal_count: u32,
+    pub path_point_count: u32,
+    pub path_closed: u32,
+    pub time: f32,
+    pub steering: SteeringParams,
+    pub bounds: WorldBounds,
+    pub vector_field: VectorFieldParams,
+    pub trail_length: u32,
+    // slot of the trail ring buffers this step writes to
+    pub trail_head: u32,
+    // write the position to all slots of the trails instead of only the head
+    pub trail_reset: u32,
+    // Integrator
+    pub integrator: u32,
+    pub sdf: SdfParams,
 }
 
 unsafe impl bytemuck::Pod for ComputeUniforms {}
 unsafe impl bytemuck::Zeroable for ComputeUniforms {}
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FlockParams {
+    pub max_steer_force: f32,
+    pub max_speed: f32,
+    pub min_speed: f32,
+    pub view_radius: f32,
+    pub avoid_radius: f32,
+    pub align_weight: f32,
+    pub cohesion_weight: f32,
+    pub seperate_weight: f32,
+    pub collisions_avoid_dst: f32,
+    pub avoid_collision_weight: f32,
+    pub collision_avoid_force_dst: f32,
+    // how far boids bank into turns per unit of lateral acceleration
+    pub bank_weight: f32,
+    // rate at which the up vector follows the banking, in 1/s
+    pub up_smoothing: f32,
+    // full opening angle of the perception cone around the heading in radians, 2 pi sees all around
+    pub view_angle: f32,
+    // 0 weights all neighbours inside the view radius equally, 1 fades them out linearly towards it
+    pub view_falloff: f32,
+    pub _padding: f32,
+}
+
+unsafe impl bytemuck::Pod for FlockParams {}
+unsafe impl bytemuck::Zeroable for FlockParams {}
+
+impl Default for FlockParams {
+    fn default() -> Self {
+        Self {
+            max_steer_force: MAX_STEER_FORCE,
+            max_speed: MAX_SPEED,
+            min_speed: MIN_SPEED,
+            view_radius: VIEW_RADIUS,
+            avoid_radius: AVOID_RADIUS,
+            align_weight: ALIGN_WEIGHT,
+            cohesion_weight: COHESION_WEIGHT,
+            seperate_weight: SEPERATE_WEIGHT,
+            collisions_avoid_dst: COLLISIONS_AVOID_DST,
+            avoid_collision_weight: AVOID_COLLISION_WEIGHT,
+            collision_avoid_force_dst: COLLISION_AVOID_FORCE_DST,
+            bank_weight: BANK_WEIGHT,
+            up_smoothing: UP_SMOOTHING,
+            view_angle: VIEW_ANGLE,
+            view_falloff: VIEW_FALLOFF,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl FlockParams {
+    // NaN is rejected as well, RangeInclusive::contains is false for it.
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if self.view_radius.is_nan() || self.view_radius <= 0.0 {
+            // the grid hash divides by the cell size, which is the view radius
+            failure::bail!("view_radius has to be positive, got {}", self.view_radius);
+        }
+        if self.avoid_radius.is_nan() || self.avoid_radius < 0.0 {
+            failure::bail!("avoid_radius can't be negative, got {}", self.avoid_radius);
+        }
+        if !(0.0..=self.max_speed).contains(&self.min_speed) {
+            failure::bail!(
+                "expected 0 <= min_speed <= max_speed, got {} and {}",
+                self.min_speed,
+                self.max_speed
+            );
+        }
+        if self.max_steer_force.is_nan() || self.max_steer_force < 0.0 {
+            failure::bail!("max_steer_force can't be negative, got {}", self.max_steer_force);
+        }
+        if !(0.0..=1.0).contains(&self.view_falloff) {
+            // a larger falloff gives negative neighbour weights
+            failure::bail!("view_falloff has to be between 0 and 1, got {}", self.view_falloff);
+        }
+        Ok(())
+    }
+}
+
 impl ComputeUniforms {
     pub fn setup_bing_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("compute_uniform_bind_group_layout"),
-            bindings: &[wgpu::BindGroupLayoutEntry::new(
-                0,
-                wgpu::ShaderStage::COMPUTE,
-                wgpu::BindingType::UniformBuffer {
-                    dynamic: false,
-                    min_binding_size: None,
-                },
-            )],
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    1,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: true,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    2,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: true,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    3,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: true,
+                    },
+                ),
+            ],
         })
     }
 
     pub fn create_bind_group(
         device: &wgpu::Device,
         buffer: &wgpu::Buffer,
+        species_rule_buffer: &wgpu::Buffer,
+        goal_buffer: &wgpu::Buffer,
+        path_buffer: &wgpu::Buffer,
         layout: Option<&wgpu::BindGroupLayout>,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: layout.unwrap_or(&Self::setup_bing_group_layout(device)),
-            bindings: &[wgpu::Binding {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
-            }],
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(species_rule_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(goal_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(path_buffer.slice(..)),
+                },
+            ],
             label: Some("compute_uniform_bind_group"),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn::BoidSpawn;
+
+    fn uniforms(boid_count: u32, params: FlockParams) -> ComputeUniforms {
+        ComputeUniforms {
+            triangle_count: 0,
+            boid_count,
+            sample_cout: 0,
+            delta: 0.01,
+            cell_size: params.view_radius,
+            cell_table_size: 0,
+            workgroup_size: 0,
+            species_count: 1,
+            params,
+            goal_count: 0,
+            path_point_count: 0,
+            path_closed: 0,
+            time: 0.0,
+            steering: SteeringParams::default(),
+            bounds: WorldBounds::none(),
+            vector_field: VectorField::zero().params(0.0),
+            trail_length: 0,
+            trail_head: 0,
+            trail_reset: 0,
+            integrator: Integrator::default() as u32,
+            sdf: SdfParams::default(),
+        }
+    }
+
+    fn step(boids: &[Boid], params: FlockParams) -> Vec<Boid> {
+        step_cpu(
+            boids,
+            &uniforms(boids.len() as u32, params),
+            &SpeciesSettings::default(),
+            &Steering::default(),
+            &VectorField::zero(),
+        )
+    }
+
+    fn boid(x: f32, vel: cgmath::Vector3<f32>) -> Boid {
+        Boid::new(cgmath::Vector3::new(x, 0.0, 0.0), vel)
+    }
+
+    #[test]
+    fn close_boids_separate() {
+        let params = FlockParams::default();
+        let vel = cgmath::Vector3::new(0.0, 0.0, 4.0);
+        let mut boids = vec![boid(0.0, vel), boid(0.5 * params.avoid_radius, vel)];
+        let distance = |boids: &[Boid]| (boids[1].position() - boids[0].position()).magnitude();
+        let before = distance(&boids);
+        for _ in 0..10 {
+            boids = step(&boids, params);
+        }
+        assert!(distance(&boids) > before);
+    }
+
+    #[test]
+    fn speed_stays_within_limits() {
+        let params = FlockParams::default();
+        let mut boids = BoidSpawn {
+            count: 64,
+            speed: 10.0,
+            ..BoidSpawn::default()
+        }
+        .generate();
+        for _ in 0..20 {
+            boids = step(&boids, params);
+            for boid in &boids {
+                let speed = boid.velocity().magnitude();
+                assert!(speed >= params.min_speed - 1e-4 && speed <= params.max_speed + 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn boids_beyond_view_radius_ignore_each_other() {
+        let params = FlockParams::default();
+        let a = boid(0.0, cgmath::Vector3::new(0.0, 0.0, 4.0));
+        let b = boid(4.0 * params.view_radius, cgmath::Vector3::new(0.0, 4.0, 0.0));
+        let together = step(&[a, b], params);
+        let alone = step(&[a], params);
+        assert_eq!(together[0].pos, alone[0].pos);
+        assert_eq!(together[0].vel, alone[0].vel);
+        assert_eq!(together[0].neighbours, 0);
+    }
+}
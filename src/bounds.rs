@@ -0,0 +1,330 @@
+use super::model::Vertex;
+use super::point_cloud::Point;
+use super::state::*;
+use super::uniforms::*;
+use cgmath::InnerSpace;
+use include_glsl::include_glsl;
+
+// Has to match the BOUNDS_* defines in boids.comp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoundsShape {
+    None = 0,
+    Box = 1,
+    Sphere = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoundaryMode {
+    // boids leaving on one side come back in on the opposite side
+    Wrap = 0,
+    // boids bounce off the boundary
+    Reflect = 1,
+    // boids are steered back towards the interior once they get within `margin` of the boundary
+    Steer = 2,
+}
+
+// World volume the boids are kept in, part of the ComputeUniforms.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct WorldBounds {
+    // w is the radius of a sphere
+    pub center: [f32; 4],
+    pub half_extent: [f32; 4],
+    pub shape: u32,
+    pub mode: u32,
+    pub margin: f32,
+    pub weight: f32,
+}
+
+unsafe impl bytemuck::Pod for WorldBounds {}
+unsafe impl bytemuck::Zeroable for WorldBounds {}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl WorldBounds {
+    pub fn none() -> Self {
+        Self {
+            center: [0.0; 4],
+            half_extent: [0.0; 4],
+            shape: BoundsShape::None as u32,
+            mode: BoundaryMode::Wrap as u32,
+            margin: 0.0,
+            weight: 0.0,
+        }
+    }
+
+    // The half extents have to be positive, wrapping divides by them.
+    pub fn aabb(
+        center: cgmath::Vector3<f32>,
+        half_extent: cgmath::Vector3<f32>,
+        mode: BoundaryMode,
+    ) -> Result<Self, failure::Error> {
+        if !(half_extent.x > 0.0 && half_extent.y > 0.0 && half_extent.z > 0.0) {
+            failure::bail!("bounds half extent {:?} has to be positive", half_extent);
+        }
+        Ok(Self {
+            center: [center.x, center.y, center.z, 0.0],
+            half_extent: [half_extent.x, half_extent.y, half_extent.z, 0.0],
+            shape: BoundsShape::Box as u32,
+            mode: mode as u32,
+            margin: 2.0,
+            weight: 10.0,
+        })
+    }
+
+    pub fn sphere(
+        center: cgmath::Vector3<f32>,
+        radius: f32,
+        mode: BoundaryMode,
+    ) -> Result<Self, failure::Error> {
+        if radius.is_nan() || radius <= 0.0 {
+            failure::bail!("bounds radius {} has to be positive", radius);
+        }
+        Ok(Self {
+            center: [center.x, center.y, center.z, radius],
+            half_extent: [radius, radius, radius, 0.0],
+            shape: BoundsShape::Sphere as u32,
+            mode: mode as u32,
+            margin: 2.0,
+            weight: 10.0,
+        })
+    }
+
+    // Only used by BoundaryMode::Steer.
+    pub fn with_steering(mut self, margin: f32, weight: f32) -> Self {
+        self.margin = margin;
+        self.weight = weight;
+        self
+    }
+
+    pub fn center(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.center[0], self.center[1], self.center[2])
+    }
+
+    pub fn half_extent(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(
+            self.half_extent[0],
+            self.half_extent[1],
+            self.half_extent[2],
+        )
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.center[3]
+    }
+
+    // Direction back into the volume, scaled from 0 at `margin` inside the boundary to 1 on it.
+    pub fn inward(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let d = pos - self.center();
+        let mut inward = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        if self.shape == BoundsShape::Box as u32 {
+            let h = self.half_extent();
+            for axis in 0..3 {
+                let to_wall = h[axis] - d[axis].abs();
+                if to_wall < self.margin {
+                    inward[axis] -= d[axis].signum() * (1.0 - to_wall / self.margin);
+                }
+            }
+        } else if self.shape == BoundsShape::Sphere as u32 {
+            let l = d.magnitude();
+            let to_wall = self.radius() - l;
+            if to_wall < self.margin && l > 0.0 {
+                inward = -d / l * (1.0 - to_wall / self.margin);
+            }
+        }
+        inward
+    }
+
+    // Applies wrapping and reflection to a position after it has been integrated.
+    pub fn constrain(
+        &self,
+        pos: cgmath::Vector3<f32>,
+        vel: cgmath::Vector3<f32>,
+    ) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        if self.shape == BoundsShape::None as u32 || self.mode == BoundaryMode::Steer as u32 {
+            return (pos, vel);
+        }
+        let c = self.center();
+        let mut d = pos - c;
+        let mut vel = vel;
+        if self.shape == BoundsShape::Box as u32 {
+            let h = self.half_extent();
+            for axis in 0..3 {
+                if self.mode == BoundaryMode::Wrap as u32 {
+                    // GLSL mod, the result has the sign of the divisor
+                    let x = d[axis] + h[axis];
+                    let y = 2.0 * h[axis];
+                    d[axis] = x - y * (x / y).floor() - h[axis];
+                } else if d[axis] > h[axis] {
+                    d[axis] = (2.0 * h[axis] - d[axis]).max(-h[axis]);
+                    vel[axis] = -vel[axis].abs();
+                } else if d[axis] < -h[axis] {
+                    d[axis] = (-2.0 * h[axis] - d[axis]).min(h[axis]);
+                    vel[axis] = vel[axis].abs();
+                }
+            }
+        } else {
+            let r = self.radius();
+            let l = d.magnitude();
+            if l > r {
+                let n = d / l;
+                if self.mode == BoundaryMode::Wrap as u32 {
+                    d = -n * (2.0 * r - l).max(0.0);
+                } else {
+                    d = n * (2.0 * r - l).max(0.0);
+                    if vel.dot(n) > 0.0 {
+                        vel -= 2.0 * vel.dot(n) * n;
+                    }
+                }
+            }
+        }
+        (c + d, vel)
+    }
+
+    // Line list outlining the bounds. Spheres are drawn as three great circles.
+    pub fn wireframe(&self) -> Vec<Point> {
+        let c = self.center();
+        let point = |p: cgmath::Vector3<f32>| Point {
+            pos: [p.x, p.y, p.z, 1.0],
+        };
+        if self.shape == BoundsShape::Box as u32 {
+            let h = self.half_extent();
+            let corner = |i: usize| {
+                c + cgmath::Vector3::new(
+                    if i & 1 == 0 { -h.x } else { h.x },
+                    if i & 2 == 0 { -h.y } else { h.y },
+                    if i & 4 == 0 { -h.z } else { h.z },
+                )
+            };
+            let mut lines = Vec::new();
+            for i in 0..8 {
+                for bit in &[1, 2, 4] {
+                    if i & bit == 0 {
+                        lines.push(point(corner(i)));
+                        lines.push(point(corner(i | bit)));
+                    }
+                }
+            }
+            lines
+        } else if self.shape == BoundsShape::Sphere as u32 {
+            const SEGMENTS: usize = 64;
+            let r = self.radius();
+            let circle = |a: f32| (a.cos() * r, a.sin() * r);
+            let mut lines = Vec::new();
+            for i in 0..SEGMENTS {
+                let a0 = i as f32 / SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                let a1 = (i + 1) as f32 / SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                let ((x0, y0), (x1, y1)) = (circle(a0), circle(a1));
+                lines.push(point(c + cgmath::Vector3::new(x0, y0, 0.0)));
+                lines.push(point(c + cgmath::Vector3::new(x1, y1, 0.0)));
+                lines.push(point(c + cgmath::Vector3::new(x0, 0.0, y0)));
+                lines.push(point(c + cgmath::Vector3::new(x1, 0.0, y1)));
+                lines.push(point(c + cgmath::Vector3::new(0.0, x0, y0)));
+                lines.push(point(c + cgmath::Vector3::new(0.0, x1, y1)));
+            }
+            lines
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+// Debug rendering of the WorldBounds.
+pub struct BoundsWireframe {
+    pub vertex_buffer: wgpu::Buffer,
+    pub num_vertices: u32,
+}
+
+impl BoundsWireframe {
+    pub fn new(device: &wgpu::Device, bounds: &WorldBounds) -> Self {
+        let mut vertices = bounds.wireframe();
+        let num_vertices = vertices.len() as u32;
+        // vertex buffers can't be empty
+        if vertices.is_empty() {
+            vertices.push(Point { pos: [0.0; 4] });
+        }
+        let vertex_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+        Self {
+            vertex_buffer,
+            num_vertices,
+        }
+    }
+}
+
+impl Renderable for BoundsWireframe {
+    fn setup_shader(device: &wgpu::Device) -> (wgpu::ShaderModule, Option<wgpu::ShaderModule>) {
+        (
+            Self::create_shader_module(device, include_glsl!("../shaders/wireframe.vert")),
+            Some(Self::create_shader_module(
+                device,
+                include_glsl!("../shaders/wireframe.frag"),
+            )),
+        )
+    }
+    fn setup_bind_group_layouts(device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
+        vec![Uniforms::setup_bing_group_layout(device)]
+    }
+    fn setup_vertex_input<'a>() -> Vec<wgpu::VertexBufferDescriptor<'a>> {
+        vec![Point::desc()]
+    }
+    fn setup_default_render_pipeline(
+        device: &wgpu::Device,
+        layouts: Option<&[&wgpu::BindGroupLayout]>,
+        format: Option<wgpu::TextureFormat>,
+        shaders: Option<(&wgpu::ShaderModule, Option<&wgpu::ShaderModule>)>,
+    ) -> wgpu::RenderPipeline {
+        if shaders.is_some() {
+            Self::create_render_pipeline(
+                device,
+                layouts.unwrap_or(
+                    &Self::setup_bind_group_layouts(device)
+                        .iter()
+                        .collect::<Vec<&wgpu::BindGroupLayout>>(),
+                ),
+                format.unwrap(),
+                shaders.unwrap(),
+                wgpu::PrimitiveTopology::LineList,
+                Self::setup_vertex_input().as_ref(),
+            )
+        } else {
+            let (vs, fs) = Self::setup_shader(device);
+            Self::create_render_pipeline(
+                device,
+                layouts.unwrap_or(
+                    &Self::setup_bind_group_layouts(device)
+                        .iter()
+                        .collect::<Vec<&wgpu::BindGroupLayout>>(),
+                ),
+                format.unwrap(),
+                (&vs, fs.as_ref()),
+                wgpu::PrimitiveTopology::LineList,
+                Self::setup_vertex_input().as_ref(),
+            )
+        }
+    }
+}
+
+pub trait DrawWireframe<'a, 'b>
+where
+    'b: 'a,
+{
+    fn draw_wireframe(&mut self, wireframe: &'b BoundsWireframe, uniforms: &'b wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawWireframe<'a, 'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_wireframe(&mut self, wireframe: &'b BoundsWireframe, uniforms: &'b wgpu::BindGroup) {
+        self.set_vertex_buffer(0, wireframe.vertex_buffer.slice(..));
+        self.set_bind_group(0, &uniforms, &[]);
+        self.draw(0..wireframe.num_vertices, 0..1);
+    }
+}
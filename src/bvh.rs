@@ -0,0 +1,364 @@
+use super::model::Mesh;
+use cgmath::InnerSpace;
+
+// Bounding volume hierarchy over the scene triangles, traversed by boids.comp for the collision avoidance.
+// The nodes are stored flattened in one buffer, the two children of an interior node are always next to each other,
+// so a node only needs to store the index of its left child. Leaves instead reference a contiguous range of
+// `triangles`, which are reordered accordingly.
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub triangles: Vec<BvhTriangle>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    // index of the left child for interior nodes and of the first triangle for leaves
+    pub left_first: u32,
+    pub max: [f32; 3],
+    // number of triangles, 0 for interior nodes
+    pub count: u32,
+}
+
+unsafe impl bytemuck::Pod for BvhNode {}
+unsafe impl bytemuck::Zeroable for BvhNode {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct BvhTriangle {
+    pub v0: [f32; 4],
+    pub v1: [f32; 4],
+    pub v2: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for BvhTriangle {}
+unsafe impl bytemuck::Zeroable for BvhTriangle {}
+
+impl BvhTriangle {
+    fn vertex(&self, i: usize) -> [f32; 4] {
+        match i {
+            0 => self.v0,
+            1 => self.v1,
+            _ => self.v2,
+        }
+    }
+
+    fn centroid(&self, axis: usize) -> f32 {
+        (self.v0[axis] + self.v1[axis] + self.v2[axis]) / 3.0
+    }
+
+    fn corners(&self) -> [cgmath::Vector3<f32>; 3] {
+        let v = |p: [f32; 4]| cgmath::Vector3::new(p[0], p[1], p[2]);
+        [v(self.v0), v(self.v1), v(self.v2)]
+    }
+
+    // Ericson, Real-Time Collision Detection 5.1.5
+    fn closest_point(&self, p: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let [a, b, c] = self.corners();
+        let ab = b - a;
+        let ac = c - a;
+        let d1 = ab.dot(p - a);
+        let d2 = ac.dot(p - a);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+        let d3 = ab.dot(p - b);
+        let d4 = ac.dot(p - b);
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+        let d5 = ab.dot(p - c);
+        let d6 = ac.dot(p - c);
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+        let denom = 1.0 / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+
+    // Möller-Trumbore, only hits in front of the origin count.
+    fn intersects(&self, origin: cgmath::Vector3<f32>, dir: cgmath::Vector3<f32>) -> bool {
+        let [a, b, c] = self.corners();
+        let e1 = b - a;
+        let e2 = c - a;
+        let p = dir.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < 1e-8 {
+            return false;
+        }
+        let s = origin - a;
+        let u = s.dot(p) / det;
+        if !(0.0..=1.0).contains(&u) {
+            return false;
+        }
+        let q = s.cross(e1);
+        let v = dir.dot(q) / det;
+        if v < 0.0 || u + v > 1.0 {
+            return false;
+        }
+        e2.dot(q) / det > 0.0
+    }
+}
+
+impl BvhNode {
+    fn distance2(&self, p: cgmath::Vector3<f32>) -> f32 {
+        let mut d2 = 0.0;
+        for axis in 0..3 {
+            let d = (self.min[axis] - p[axis]).max(p[axis] - self.max[axis]).max(0.0);
+            d2 += d * d;
+        }
+        d2
+    }
+
+    // slab test
+    fn intersects(&self, origin: cgmath::Vector3<f32>, dir: cgmath::Vector3<f32>) -> bool {
+        let mut t_min = 0.0f32;
+        let mut t_max = std::f32::MAX;
+        for axis in 0..3 {
+            let inv = 1.0 / dir[axis];
+            let t0 = (self.min[axis] - origin[axis]) * inv;
+            let t1 = (self.max[axis] - origin[axis]) * inv;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+        t_min <= t_max
+    }
+}
+
+impl Bvh {
+    pub const MAX_LEAF_SIZE: usize = 4;
+    // Has to match BVH_STACK_SIZE in boids.comp.
+    pub const MAX_STACK_SIZE: usize = 32;
+
+    pub fn from_meshes(meshes: &[Mesh]) -> Result<Self, failure::Error> {
+        let triangles = meshes
+            .iter()
+            .flat_map(|mesh| {
+                mesh.indices.chunks_exact(3).map(move |t| {
+                    let vertex = |i: u32| {
+                        let p = mesh.positions[i as usize];
+                        [p[0], p[1], p[2], 1.0]
+                    };
+                    BvhTriangle {
+                        v0: vertex(t[0]),
+                        v1: vertex(t[1]),
+                        v2: vertex(t[2]),
+                    }
+                })
+            })
+            .collect();
+        Self::from_triangles(triangles)
+    }
+
+    // Fails if the tree is too deep for the traversal stack in boids.comp, which would skip
+    // the nodes that don't fit.
+    pub fn from_triangles(mut triangles: Vec<BvhTriangle>) -> Result<Self, failure::Error> {
+        let mut nodes = vec![Self::leaf(&triangles, 0, triangles.len())];
+        if !triangles.is_empty() {
+            Self::subdivide(&mut nodes, &mut triangles, 0);
+        }
+        let bvh = Self { nodes, triangles };
+        // walking down to a node at depth d leaves at most one sibling per level on the stack,
+        // so the children of the deepest interior nodes need depth + 1 slots
+        if bvh.depth() + 1 > Self::MAX_STACK_SIZE {
+            failure::bail!(
+                "scene BVH is {} levels deep, boids.comp can only traverse {}",
+                bvh.depth(),
+                Self::MAX_STACK_SIZE - 1
+            );
+        }
+        Ok(bvh)
+    }
+
+    // Number of edges from the root to the deepest leaf.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((node, d)) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.count == 0 && !self.triangles.is_empty() {
+                let first = node.left_first as usize;
+                stack.push((first, d + 1));
+                stack.push((first + 1, d + 1));
+            } else {
+                depth = depth.max(d);
+            }
+        }
+        depth
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.triangles.len() as u32
+    }
+
+    // Bounds of all triangles, None for an empty scene.
+    pub fn bounds(&self) -> Option<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let root = &self.nodes[0];
+        Some((root.min.into(), root.max.into()))
+    }
+
+    // Distance from `p` to the closest triangle, infinite for an empty scene.
+    pub fn distance(&self, p: cgmath::Vector3<f32>) -> f32 {
+        let mut best2 = std::f32::INFINITY;
+        if self.triangles.is_empty() {
+            return best2;
+        }
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.distance2(p) >= best2 {
+                continue;
+            }
+            let (first, count) = (node.left_first as usize, node.count as usize);
+            if count > 0 {
+                for triangle in &self.triangles[first..first + count] {
+                    best2 = best2.min((triangle.closest_point(p) - p).magnitude2());
+                }
+            } else {
+                stack.push(first);
+                stack.push(first + 1);
+            }
+        }
+        best2.sqrt()
+    }
+
+    // Number of triangles the ray from `origin` along `dir` crosses.
+    pub fn count_hits(&self, origin: cgmath::Vector3<f32>, dir: cgmath::Vector3<f32>) -> u32 {
+        if self.triangles.is_empty() {
+            return 0;
+        }
+        let mut hits = 0;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !node.intersects(origin, dir) {
+                continue;
+            }
+            let (first, count) = (node.left_first as usize, node.count as usize);
+            if count > 0 {
+                hits += self.triangles[first..first + count]
+                    .iter()
+                    .filter(|t| t.intersects(origin, dir))
+                    .count() as u32;
+            } else {
+                stack.push(first);
+                stack.push(first + 1);
+            }
+        }
+        hits
+    }
+
+    // Whether `p` lies inside a closed mesh. Rays in three directions vote, so a ray grazing an
+    // edge and counting a crossing twice doesn't flip the result.
+    pub fn contains(&self, p: cgmath::Vector3<f32>) -> bool {
+        let dirs = [
+            cgmath::Vector3::new(1.0, 0.0123, 0.0071),
+            cgmath::Vector3::new(0.0089, 1.0, 0.0137),
+            cgmath::Vector3::new(0.0113, 0.0067, 1.0),
+        ];
+        dirs.iter().filter(|&&dir| self.count_hits(p, dir) % 2 == 1).count() >= 2
+    }
+
+    // Storage buffers can't be empty, so an empty scene is uploaded as a single empty leaf and a degenerate triangle.
+    pub fn create_buffers(&self, device: &wgpu::Device) -> (wgpu::Buffer, wgpu::Buffer) {
+        let empty_triangle = [BvhTriangle {
+            v0: [0.0; 4],
+            v1: [0.0; 4],
+            v2: [0.0; 4],
+        }];
+        let triangles: &[BvhTriangle] = if self.triangles.is_empty() {
+            &empty_triangle
+        } else {
+            &self.triangles
+        };
+        let node_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&self.nodes),
+            wgpu::BufferUsage::STORAGE,
+        );
+        let triangle_buffer = device
+            .create_buffer_with_data(bytemuck::cast_slice(triangles), wgpu::BufferUsage::STORAGE);
+        (node_buffer, triangle_buffer)
+    }
+
+    fn leaf(triangles: &[BvhTriangle], first: usize, count: usize) -> BvhNode {
+        let mut min = [std::f32::MAX; 3];
+        let mut max = [std::f32::MIN; 3];
+        for triangle in &triangles[first..first + count] {
+            for i in 0..3 {
+                let v = triangle.vertex(i);
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(v[axis]);
+                    max[axis] = max[axis].max(v[axis]);
+                }
+            }
+        }
+        if count == 0 {
+            min = [0.0; 3];
+            max = [0.0; 3];
+        }
+        BvhNode {
+            min,
+            left_first: first as u32,
+            max,
+            count: count as u32,
+        }
+    }
+
+    // Median split along the longest axis of the node bounds.
+    fn subdivide(nodes: &mut Vec<BvhNode>, triangles: &mut [BvhTriangle], node: usize) {
+        let BvhNode {
+            min,
+            left_first,
+            max,
+            count,
+        } = nodes[node];
+        let (first, count) = (left_first as usize, count as usize);
+        if count <= Self::MAX_LEAF_SIZE {
+            return;
+        }
+
+        let extent = [max[0] - min[0], max[1] - min[1], max[2] - min[2]];
+        let axis = if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+        triangles[first..first + count].sort_by(|a, b| {
+            a.centroid(axis)
+                .partial_cmp(&b.centroid(axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left_count = count / 2;
+        let left = nodes.len();
+        nodes.push(Self::leaf(triangles, first, left_count));
+        nodes.push(Self::leaf(
+            triangles,
+            first + left_count,
+            count - left_count,
+        ));
+        nodes[node].left_first = left as u32;
+        nodes[node].count = 0;
+
+        Self::subdivide(nodes, triangles, left);
+        Self::subdivide(nodes, triangles, left + 1);
+    }
+}
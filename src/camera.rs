@@ -28,7 +28,25 @@ impl Camera {
     }
 }
 
+// Scroll lines needed to halve or double the distance to the target in orbit mode.
+const ORBIT_ZOOM_LINES: f32 = 5.0;
+// Pixel scroll deltas are converted to lines with this many pixels per line.
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+const ORBIT_MIN_DISTANCE: f32 = 0.5;
+// Keeps the eye from passing over the poles, where looking at the target flips the view.
+const ORBIT_MIN_POLAR_ANGLE: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // WASD moves the eye and the target, the mouse turns the target around the eye while grabbed
+    FreeFly,
+    // left drag turns the eye around the target, middle drag pans and scrolling zooms
+    Orbit,
+}
+
 pub struct CameraController {
+    pub mode: CameraMode,
+    pub mode_can_be_switched: bool,
     pub movement_speed: f32,
     pub mouse_speed: f32,
     pub is_up_pressed: bool,
@@ -42,11 +60,19 @@ pub struct CameraController {
     pub x_delta: f64,
     pub y_delta: f64,
     pub ctrl: bool,
+    pub is_left_mouse_pressed: bool,
+    pub is_middle_mouse_pressed: bool,
+    pub pan_x_delta: f64,
+    pub pan_y_delta: f64,
+    // in lines, positive towards the target
+    pub scroll_delta: f32,
 }
 
 impl CameraController {
     pub fn new(movement_speed: f32, mouse_speed: f32) -> Self {
         Self {
+            mode: CameraMode::FreeFly,
+            mode_can_be_switched: true,
             movement_speed,
             mouse_speed,
             is_up_pressed: false,
@@ -60,6 +86,11 @@ impl CameraController {
             x_delta: 0.0,
             y_delta: 0.0,
             ctrl: false,
+            is_left_mouse_pressed: false,
+            is_middle_mouse_pressed: false,
+            pan_x_delta: 0.0,
+            pan_y_delta: 0.0,
+            scroll_delta: 0.0,
         }
     }
 
@@ -101,7 +132,19 @@ impl CameraController {
                             self.is_right_pressend = is_pressed;
                             true
                         }
-                        VirtualKeyCode::G => {
+                        VirtualKeyCode::Tab => {
+                            if self.mode_can_be_switched && is_pressed {
+                                self.switch_mode();
+                                self.mode_can_be_switched = false;
+                                true
+                            } else if !is_pressed {
+                                self.mode_can_be_switched = true;
+                                true
+                            } else {
+                                false
+                            }
+                        }
+                        VirtualKeyCode::G if self.mode == CameraMode::FreeFly => {
                             if self.mouse_can_be_activated && is_pressed {
                                 self.is_mouse_activated = !self.is_mouse_activated;
                                 self.mouse_can_be_activated = false;
@@ -116,6 +159,27 @@ impl CameraController {
                         _ => false,
                     }
                 }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let is_pressed = *state == ElementState::Pressed;
+                    match button {
+                        MouseButton::Left => {
+                            self.is_left_mouse_pressed = is_pressed;
+                            self.mode == CameraMode::Orbit
+                        }
+                        MouseButton::Middle => {
+                            self.is_middle_mouse_pressed = is_pressed;
+                            self.mode == CameraMode::Orbit
+                        }
+                        _ => false,
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } if self.mode == CameraMode::Orbit => {
+                    self.scroll_delta += match delta {
+                        MouseScrollDelta::LineDelta(_, y) => *y,
+                        MouseScrollDelta::PixelDelta(p) => (p.y / PIXELS_PER_SCROLL_LINE) as f32,
+                    };
+                    true
+                }
                 WindowEvent::ModifiersChanged(m) => {
                     if self.ctrl != m.ctrl() {
                         self.ctrl = m.ctrl();
@@ -127,28 +191,53 @@ impl CameraController {
                 _ => false,
             },
             Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion { delta: (x, y) } => {
-                    if self.is_mouse_activated {
+                DeviceEvent::MouseMotion { delta: (x, y) } => match self.mode {
+                    CameraMode::FreeFly if self.is_mouse_activated => {
                         self.x_delta += x;
                         self.y_delta += y;
                         true
-                    } else {
-                        false
                     }
-                }
+                    CameraMode::Orbit if self.is_middle_mouse_pressed => {
+                        self.pan_x_delta += x;
+                        self.pan_y_delta += y;
+                        true
+                    }
+                    CameraMode::Orbit if self.is_left_mouse_pressed => {
+                        self.x_delta += x;
+                        self.y_delta += y;
+                        true
+                    }
+                    _ => false,
+                },
                 _ => false,
             },
             _ => false,
         }
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera) {
+    // Releases the mouse when leaving free-fly, orbit mode drags with a visible cursor instead.
+    pub fn switch_mode(&mut self) {
+        self.mode = match self.mode {
+            CameraMode::FreeFly => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::FreeFly,
+        };
+        self.is_mouse_activated = false;
+        self.x_delta = 0.0;
+        self.y_delta = 0.0;
+        self.pan_x_delta = 0.0;
+        self.pan_y_delta = 0.0;
+        self.scroll_delta = 0.0;
+    }
+
+    // `delta` is the frame time in seconds, `movement_speed` is in units per second.
+    // The mouse deltas are accumulated over the frame, so looking around doesn't need to be scaled.
+    pub fn update_camera(&mut self, camera: &mut Camera, delta: f32) {
         let forward = (camera.target - camera.eye).normalize();
         let speed = if self.ctrl {
             self.movement_speed * 5.0
         } else {
             self.movement_speed
-        };
+        } * delta;
 
         if self.is_forward_pressed {
             camera.eye += forward * speed;
@@ -179,6 +268,18 @@ impl CameraController {
             camera.target -= camera.up * speed;
         }
 
+        match self.mode {
+            CameraMode::FreeFly => self.update_free_fly(camera, right),
+            CameraMode::Orbit => self.update_orbit(camera, right),
+        }
+        self.x_delta = 0.0;
+        self.y_delta = 0.0;
+        self.pan_x_delta = 0.0;
+        self.pan_y_delta = 0.0;
+        self.scroll_delta = 0.0;
+    }
+
+    fn update_free_fly(&self, camera: &mut Camera, right: cgmath::Vector3<f32>) {
         let pitch = cgmath::Matrix3::from_axis_angle(
             right,
             cgmath::Deg(-self.y_delta as f32 * 0.01 * self.mouse_speed),
@@ -190,7 +291,34 @@ impl CameraController {
         camera.target = camera.eye + pitch * yaw * (camera.target - camera.eye);
         //camera.up = pitch * yaw * camera.up;
         //println!("{:#?}", (self.x_delta, self.y_delta));
-        self.x_delta = 0.0;
-        self.y_delta = 0.0;
+    }
+
+    // Turns the eye around the target, then zooms and pans. Panning moves the target with the
+    // eye and scales with the distance, so the scene follows the cursor roughly at the target.
+    fn update_orbit(&self, camera: &mut Camera, right: cgmath::Vector3<f32>) {
+        let up = camera.up.normalize();
+        let offset = camera.eye - camera.target;
+
+        // dragging turns the scene along with the cursor
+        let yaw = cgmath::Matrix3::from_axis_angle(
+            up,
+            cgmath::Deg(self.x_delta as f32 * 0.01 * self.mouse_speed),
+        );
+        let polar = cgmath::Deg::from(up.angle(offset)).0;
+        let pitch = (-self.y_delta as f32 * 0.01 * self.mouse_speed)
+            .max(ORBIT_MIN_POLAR_ANGLE - polar)
+            .min(180.0 - ORBIT_MIN_POLAR_ANGLE - polar);
+        let pitch = cgmath::Matrix3::from_axis_angle(right.normalize(), cgmath::Deg(pitch));
+        let direction = (yaw * pitch * offset).normalize();
+
+        let distance = (offset.magnitude() * 0.5f32.powf(self.scroll_delta / ORBIT_ZOOM_LINES))
+            .max(ORBIT_MIN_DISTANCE);
+
+        let right = (-direction).cross(up).normalize();
+        let screen_up = right.cross(-direction);
+        let pan_speed = distance * 0.0002 * self.mouse_speed;
+        camera.target += right * (-self.pan_x_delta as f32 * pan_speed)
+            + screen_up * (self.pan_y_delta as f32 * pan_speed);
+        camera.eye = camera.target + direction * distance;
     }
 }
\ No newline at end of file
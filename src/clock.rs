@@ -0,0 +1,46 @@
+use std::time::Instant;
+
+// Measures the real time between frames and splits it into fixed simulation steps.
+// Time that doesn't make up a full step is carried over to the next frame.
+pub struct Clock {
+    last_tick: Instant,
+    accumulator: f32,
+    pub fixed_delta: f32,
+    // Upper bound of steps per frame, so a slow frame doesn't make the next one even slower.
+    pub max_steps: u32,
+}
+
+impl Clock {
+    pub fn new(fixed_delta: f32, max_steps: u32) -> Self {
+        Self {
+            last_tick: Instant::now(),
+            accumulator: 0.0,
+            fixed_delta,
+            max_steps,
+        }
+    }
+
+    // Returns the real time since the last tick in seconds and the number of simulation steps to run.
+    pub fn tick(&mut self) -> (f32, u32) {
+        let now = Instant::now();
+        let frame_delta = now.duration_since(self.last_tick).as_secs_f32();
+        self.last_tick = now;
+
+        self.accumulator += frame_delta;
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_delta && steps < self.max_steps {
+            self.accumulator -= self.fixed_delta;
+            steps += 1;
+        }
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.fixed_delta);
+        }
+
+        (frame_delta, steps)
+    }
+
+    // How far the current frame is between the last and the next simulation step, in [0, 1].
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.fixed_delta).min(1.0)
+    }
+}
@@ -0,0 +1,107 @@
+use super::boids::{MAX_SPEED, MIN_SPEED};
+
+// Has to match the COLOR_* defines in boids.vert.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    // the species colors of the SpeciesSettings
+    Species = 0,
+    Speed = 1,
+    // the direction of flight as rgb, doesn't use the colormap
+    Heading = 2,
+    // number of boids in the perception cone, counted by the compute pass
+    Neighbours = 3,
+    // a random color per boid
+    Id = 4,
+    // a random color per group of connected boids, see the cluster labels in boids.comp
+    Cluster = 5,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 6] = [
+        ColorMode::Species,
+        ColorMode::Speed,
+        ColorMode::Heading,
+        ColorMode::Neighbours,
+        ColorMode::Id,
+        ColorMode::Cluster,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+// Has to match the COLORMAP_* defines in boids.vert.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Colormap {
+    Viridis = 0,
+    Magma = 1,
+}
+
+impl Colormap {
+    pub fn next(self) -> Self {
+        match self {
+            Colormap::Viridis => Colormap::Magma,
+            Colormap::Magma => Colormap::Viridis,
+        }
+    }
+}
+
+// Uniform of the boids render pipeline selecting how the boids are colored.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ColorSettings {
+    pub mode: u32,
+    pub colormap: u32,
+    // speed and neighbour count are mapped from min..max to the start and end of the colormap
+    pub min: f32,
+    pub max: f32,
+}
+
+unsafe impl bytemuck::Pod for ColorSettings {}
+unsafe impl bytemuck::Zeroable for ColorSettings {}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self::new(ColorMode::Species, Colormap::Viridis)
+    }
+}
+
+impl ColorSettings {
+    // Uses a range fitting the default FlockParams.
+    pub fn new(mode: ColorMode, colormap: Colormap) -> Self {
+        let (min, max) = match mode {
+            ColorMode::Speed => (MIN_SPEED, MAX_SPEED),
+            ColorMode::Neighbours => (0.0, 16.0),
+            _ => (0.0, 1.0),
+        };
+        Self {
+            mode: mode as u32,
+            colormap: colormap as u32,
+            min,
+            max,
+        }
+    }
+
+    pub fn with_range(mut self, min: f32, max: f32) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn mode(&self) -> ColorMode {
+        ColorMode::ALL
+            .iter()
+            .copied()
+            .find(|&m| m as u32 == self.mode)
+            .unwrap_or(ColorMode::Species)
+    }
+
+    pub fn colormap(&self) -> Colormap {
+        if self.colormap == Colormap::Magma as u32 {
+            Colormap::Magma
+        } else {
+            Colormap::Viridis
+        }
+    }
+}
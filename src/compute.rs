@@ -0,0 +1,84 @@
+// Helpers shared by the compute pipelines.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WorkgroupSize {
+    Size64 = 64,
+    Size128 = 128,
+    Size256 = 256,
+}
+
+impl WorkgroupSize {
+    pub fn get(self) -> u32 {
+        self as u32
+    }
+}
+
+impl Default for WorkgroupSize {
+    fn default() -> Self {
+        WorkgroupSize::Size128
+    }
+}
+
+// wgpu::Limits doesn't expose the maximum number of workgroups per dispatch dimension,
+// so we use the minimum every backend guarantees.
+pub const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+// Number of workgroups to dispatch so that there is one invocation per item.
+// Counts that don't fit into the x dimension are spread over y, the shaders then compute their index as
+// `gl_GlobalInvocationID.x + gl_GlobalInvocationID.y * gl_NumWorkGroups.x * workgroup_size`.
+pub fn dispatch_size(count: u32, workgroup_size: WorkgroupSize) -> (u32, u32, u32) {
+    let groups = (count + workgroup_size.get() - 1) / workgroup_size.get();
+    if groups <= MAX_WORKGROUPS_PER_DIMENSION {
+        (groups.max(1), 1, 1)
+    } else {
+        (
+            MAX_WORKGROUPS_PER_DIMENSION,
+            (groups + MAX_WORKGROUPS_PER_DIMENSION - 1) / MAX_WORKGROUPS_PER_DIMENSION,
+            1,
+        )
+    }
+}
+
+const SPIRV_HEADER_LEN: usize = 5;
+const OP_EXECUTION_MODE: u32 = 16;
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+// wgpu doesn't support specialization constants, so the workgroup size is patched directly into the
+// `OpExecutionMode %main LocalSize x y z` instruction of the compiled shader.
+// This only works for shaders that don't read gl_WorkGroupSize, which is compiled into a separate constant.
+pub fn with_local_size_x(code: &[u32], local_size_x: u32) -> Vec<u32> {
+    let mut code = code.to_vec();
+    let mut i = SPIRV_HEADER_LEN;
+    while i < code.len() {
+        let word_count = (code[i] >> 16) as usize;
+        let opcode = code[i] & 0xffff;
+        if opcode == OP_EXECUTION_MODE && code[i + 2] == EXECUTION_MODE_LOCAL_SIZE {
+            code[i + 3] = local_size_x;
+        }
+        if word_count == 0 {
+            break;
+        }
+        i += word_count;
+    }
+    code
+}
+
+pub fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    code: &[u32],
+    workgroup_size: Option<WorkgroupSize>,
+) -> wgpu::ComputePipeline {
+    let code = match workgroup_size {
+        Some(workgroup_size) => with_local_size_x(code, workgroup_size.get()),
+        None => code.to_vec(),
+    };
+    let module = device.create_shader_module(wgpu::util::make_spirv(bytemuck::cast_slice(&code)));
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        layout,
+        compute_stage: wgpu::ProgrammableStageDescriptor {
+            module: &module,
+            entry_point: "main",
+        },
+    })
+}
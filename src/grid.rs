@@ -0,0 +1,151 @@
+use super::compute::*;
+use include_glsl::include_glsl;
+
+// Uniform grid used by boids.comp to only visit boids in neighbouring cells.
+// Cells are `cell_size` wide and hashed into a fixed size table, so the grid doesn't need any world bounds.
+// Every step the grid is rebuilt with a count-and-scan:
+// clear the per cell counters, count the boids per cell, scan the counts into start offsets and scatter the boid
+// indices into `grid_index_buffer` sorted by cell.
+pub struct BoidGrid {
+    pub table_size: u32,
+    workgroup_size: WorkgroupSize,
+
+    cell_count_buffer: wgpu::Buffer,
+    cell_start_buffer: wgpu::Buffer,
+    boid_cell_buffer: wgpu::Buffer,
+    grid_index_buffer: wgpu::Buffer,
+
+    clear_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+}
+
+impl BoidGrid {
+    // The grid buffers live in the boid bind group behind the two boid buffers.
+    pub const FIRST_BINDING: u32 = 2;
+
+    // `capacity` is the number of boids the grid has room for, see `reserve`.
+    pub fn new(
+        device: &wgpu::Device,
+        capacity: u32,
+        pipeline_layout: &wgpu::PipelineLayout,
+        workgroup_size: WorkgroupSize,
+    ) -> Self {
+        let table_size = Self::table_size_for(capacity);
+
+        let cell_count_buffer = Self::create_storage_buffer(device, table_size as usize);
+        let cell_start_buffer = Self::create_storage_buffer(device, table_size as usize);
+        // (cell, offset inside the cell) for every boid
+        let boid_cell_buffer = Self::create_storage_buffer(device, 2 * capacity as usize);
+        let grid_index_buffer = Self::create_storage_buffer(device, capacity as usize);
+
+        let clear_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/grid_clear.comp"),
+            Some(workgroup_size),
+        );
+        let count_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/grid_count.comp"),
+            Some(workgroup_size),
+        );
+        let scan_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/grid_scan.comp"),
+            None,
+        );
+        let scatter_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/grid_scatter.comp"),
+            Some(workgroup_size),
+        );
+
+        Self {
+            table_size,
+            workgroup_size,
+            cell_count_buffer,
+            cell_start_buffer,
+            boid_cell_buffer,
+            grid_index_buffer,
+            clear_pipeline,
+            count_pipeline,
+            scan_pipeline,
+            scatter_pipeline,
+        }
+    }
+
+    // Reallocates the buffers for up to `capacity` boids. The contents are rebuilt every step,
+    // so nothing has to be copied, but bind groups created from `bindings` have to be recreated.
+    pub fn reserve(&mut self, device: &wgpu::Device, capacity: u32) {
+        let table_size = Self::table_size_for(capacity);
+        if table_size != self.table_size {
+            self.table_size = table_size;
+            self.cell_count_buffer = Self::create_storage_buffer(device, table_size as usize);
+            self.cell_start_buffer = Self::create_storage_buffer(device, table_size as usize);
+        }
+        self.boid_cell_buffer = Self::create_storage_buffer(device, 2 * capacity as usize);
+        self.grid_index_buffer = Self::create_storage_buffer(device, capacity as usize);
+    }
+
+    fn table_size_for(capacity: u32) -> u32 {
+        (capacity * 2).next_power_of_two().max(1024)
+    }
+
+    pub fn setup_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        (Self::FIRST_BINDING..Self::FIRST_BINDING + 4)
+            .map(|binding| {
+                wgpu::BindGroupLayoutEntry::new(
+                    binding,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn bindings(&self) -> Vec<wgpu::Binding> {
+        vec![
+            &self.cell_count_buffer,
+            &self.cell_start_buffer,
+            &self.boid_cell_buffer,
+            &self.grid_index_buffer,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, buffer)| wgpu::Binding {
+            binding: Self::FIRST_BINDING + i as u32,
+            resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+        })
+        .collect()
+    }
+
+    // Expects the boid, scene and uniform bind groups to already be set on the pass.
+    pub fn record<'a>(&'a self, compute_pass: &mut wgpu::ComputePass<'a>, num_instances: u32) {
+        let (x, y, z) = dispatch_size(self.table_size, self.workgroup_size);
+        compute_pass.set_pipeline(&self.clear_pipeline);
+        compute_pass.dispatch(x, y, z);
+        let (x, y, z) = dispatch_size(num_instances, self.workgroup_size);
+        compute_pass.set_pipeline(&self.count_pipeline);
+        compute_pass.dispatch(x, y, z);
+        compute_pass.set_pipeline(&self.scan_pipeline);
+        compute_pass.dispatch(1, 1, 1);
+        compute_pass.set_pipeline(&self.scatter_pipeline);
+        compute_pass.dispatch(x, y, z);
+    }
+
+    fn create_storage_buffer(device: &wgpu::Device, len: usize) -> wgpu::Buffer {
+        device.create_buffer_with_data(
+            bytemuck::cast_slice(&vec![0u32; len]),
+            wgpu::BufferUsage::STORAGE,
+        )
+    }
+}
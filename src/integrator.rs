@@ -0,0 +1,58 @@
+// How boids.comp and step_cpu advance a boid by one step. Has to match the INTEGRATOR_* defines in boids.comp.
+//
+// Verlet and Rk2 evaluate the steering forces twice per step. The neighbours stay where they are
+// for the second evaluation, only the boid's own position and velocity change.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Integrator {
+    // pos += vel * delta with the velocity before the step
+    Euler = 0,
+    // pos += vel * delta with the velocity after the step, what boids.comp always did
+    SemiImplicitEuler = 1,
+    // velocity Verlet, averages the acceleration at the start and at the end of the step
+    Verlet = 2,
+    // midpoint method, uses the acceleration halfway through the step
+    Rk2 = 3,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 4] = [
+        Integrator::Euler,
+        Integrator::SemiImplicitEuler,
+        Integrator::Verlet,
+        Integrator::Rk2,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn from_u32(value: u32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|&i| i as u32 == value)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Integrator::Euler => "euler",
+            Integrator::SemiImplicitEuler => "semi-implicit-euler",
+            Integrator::Verlet => "verlet",
+            Integrator::Rk2 => "rk2",
+        }
+    }
+}
+
+impl Default for Integrator {
+    fn default() -> Self {
+        Integrator::SemiImplicitEuler
+    }
+}
+
+impl std::str::FromStr for Integrator {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::ALL.iter().copied().find(|i| i.name() == s) {
+            Some(integrator) => Ok(integrator),
+            None => failure::bail!("unknown integrator {}", s),
+        }
+    }
+}
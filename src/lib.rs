@@ -0,0 +1,24 @@
+pub mod bounds;
+pub mod camera;
+pub mod clock;
+pub mod coloring;
+pub mod compute;
+pub mod grid;
+pub mod instance;
+pub mod integrator;
+pub mod lod;
+pub mod metrics;
+pub mod model;
+pub mod state;
+pub mod steering;
+pub mod texture;
+pub mod trails;
+pub mod uniforms;
+pub mod vector_field;
+pub mod boids;
+pub mod bvh;
+pub mod point_cloud;
+pub mod recorder;
+pub mod sdf;
+pub mod spawn;
+pub mod species;
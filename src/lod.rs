@@ -0,0 +1,305 @@
+use super::boids::Boid;
+use super::compute::*;
+use super::point_cloud::Point;
+use include_glsl::include_glsl;
+
+pub const DEFAULT_LOD_DISTANCE: f32 = 30.0;
+
+// Byte offsets into the draw args buffer, the arguments of an indexed draw for the near boids
+// are followed by the ones of a plain draw for the far boids.
+pub const MESH_DRAW_ARGS_OFFSET: wgpu::BufferAddress = 0;
+pub const POINT_DRAW_ARGS_OFFSET: wgpu::BufferAddress = 5 * 4;
+
+// Has to match LodUniforms in lod.comp.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct LodUniforms {
+    eye: [f32; 4],
+    distance: f32,
+    boid_count: u32,
+    workgroup_size: u32,
+    _padding: u32,
+}
+
+unsafe impl bytemuck::Pod for LodUniforms {}
+unsafe impl bytemuck::Zeroable for LodUniforms {}
+
+// Compacted copies of the boids of one level of detail, with the previous step for the interpolation.
+struct LodInstances {
+    near: wgpu::Buffer,
+    near_previous: wgpu::Buffer,
+    far: wgpu::Buffer,
+    far_previous: wgpu::Buffer,
+}
+
+impl LodInstances {
+    fn new(device: &wgpu::Device, capacity: u32) -> Self {
+        let create = || {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("lod_instance_buffer"),
+                size: capacity as wgpu::BufferAddress
+                    * std::mem::size_of::<Boid>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            near: create(),
+            near_previous: create(),
+            far: create(),
+            far_previous: create(),
+        }
+    }
+}
+
+// Splits the boids by their distance to the camera. Every frame lod.comp copies each boid into the
+// near or the far instance buffers and counts the instances in the indirect draw arguments, so the
+// near boids can be drawn with the boid mesh and the far ones as points without reading anything back.
+pub struct BoidLod {
+    // boids further away from the camera are drawn as points
+    pub distance: f32,
+    workgroup_size: WorkgroupSize,
+
+    instances: LodInstances,
+    draw_args_buffer: wgpu::Buffer,
+    // the draw args without any instances, copied over draw_args_buffer before every classification
+    reset_draw_args_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    // a single vertex at the origin, boids.vert moves it to the boid
+    point_vertex_buffer: wgpu::Buffer,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    // like the boid bind groups, 1 reads the latest step from the first boid buffer and 2 from the second
+    bind_group1: wgpu::BindGroup,
+    bind_group2: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl BoidLod {
+    // `num_indices` is the index count of the boid mesh.
+    pub fn new(
+        device: &wgpu::Device,
+        capacity: u32,
+        num_indices: u32,
+        boid_buffer1: &wgpu::Buffer,
+        boid_buffer2: &wgpu::Buffer,
+        workgroup_size: WorkgroupSize,
+    ) -> Self {
+        let instances = LodInstances::new(device, capacity);
+        let reset_draw_args: [u32; 9] = [num_indices, 0, 0, 0, 0, 1, 0, 0, 0];
+        let draw_args_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&reset_draw_args),
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::INDIRECT | wgpu::BufferUsage::COPY_DST,
+        );
+        let reset_draw_args_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&reset_draw_args),
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("lod_uniform_buffer"),
+            size: std::mem::size_of::<LodUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let point_vertex_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[Point {
+                pos: [0.0, 0.0, 0.0, 1.0],
+            }]),
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        let bind_group_layout = Self::setup_bind_group_layout(device);
+        let (bind_group1, bind_group2) = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &instances,
+            &draw_args_buffer,
+            &uniform_buffer,
+            boid_buffer1,
+            boid_buffer2,
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let pipeline = create_compute_pipeline(
+            device,
+            &pipeline_layout,
+            include_glsl!("../shaders/lod.comp"),
+            Some(workgroup_size),
+        );
+
+        Self {
+            distance: DEFAULT_LOD_DISTANCE,
+            workgroup_size,
+            instances,
+            draw_args_buffer,
+            reset_draw_args_buffer,
+            uniform_buffer,
+            point_vertex_buffer,
+            bind_group_layout,
+            bind_group1,
+            bind_group2,
+            pipeline,
+        }
+    }
+
+    fn setup_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries: Vec<_> = (0..7)
+            .map(|binding| {
+                wgpu::BindGroupLayoutEntry::new(
+                    binding,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                )
+            })
+            .collect();
+        entries.push(wgpu::BindGroupLayoutEntry::new(
+            7,
+            wgpu::ShaderStage::COMPUTE,
+            wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: None,
+            },
+        ));
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("lod_bind_group_layout"),
+            bindings: &entries,
+        })
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        instances: &LodInstances,
+        draw_args_buffer: &wgpu::Buffer,
+        uniform_buffer: &wgpu::Buffer,
+        boid_buffer1: &wgpu::Buffer,
+        boid_buffer2: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let create = |latest: &wgpu::Buffer, previous: &wgpu::Buffer, label| {
+            let buffers = [
+                latest,
+                previous,
+                &instances.near,
+                &instances.near_previous,
+                &instances.far,
+                &instances.far_previous,
+                draw_args_buffer,
+                uniform_buffer,
+            ];
+            let bindings: Vec<_> = buffers
+                .iter()
+                .enumerate()
+                .map(|(i, buffer)| wgpu::Binding {
+                    binding: i as u32,
+                    resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+                })
+                .collect();
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                bindings: &bindings,
+                label: Some(label),
+            })
+        };
+        (
+            create(boid_buffer1, boid_buffer2, "lod_bind_group1"),
+            create(boid_buffer2, boid_buffer1, "lod_bind_group2"),
+        )
+    }
+
+    // Reallocates the instance buffers for up to `capacity` boids and binds the new boid buffers.
+    // The instances are rebuilt by every `record`, so nothing has to be copied.
+    pub fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        capacity: u32,
+        boid_buffer1: &wgpu::Buffer,
+        boid_buffer2: &wgpu::Buffer,
+    ) {
+        self.instances = LodInstances::new(device, capacity);
+        let (bind_group1, bind_group2) = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.instances,
+            &self.draw_args_buffer,
+            &self.uniform_buffer,
+            boid_buffer1,
+            boid_buffer2,
+        );
+        self.bind_group1 = bind_group1;
+        self.bind_group2 = bind_group2;
+    }
+
+    // Classifies the first `num_instances` boids. `swapped` is Boids::boid_buffer_index, if it is set
+    // the second boid buffer holds the latest step.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        eye: cgmath::Point3<f32>,
+        num_instances: u32,
+        swapped: bool,
+    ) {
+        let uniforms = LodUniforms {
+            eye: [eye.x, eye.y, eye.z, 1.0],
+            distance: self.distance,
+            boid_count: num_instances,
+            workgroup_size: self.workgroup_size.get(),
+            _padding: 0,
+        };
+        let staging_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[uniforms]),
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.uniform_buffer,
+            0,
+            std::mem::size_of::<LodUniforms>() as wgpu::BufferAddress,
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.reset_draw_args_buffer,
+            0,
+            &self.draw_args_buffer,
+            0,
+            std::mem::size_of::<[u32; 9]>() as wgpu::BufferAddress,
+        );
+
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(
+            0,
+            if swapped {
+                &self.bind_group2
+            } else {
+                &self.bind_group1
+            },
+            &[],
+        );
+        let (x, y, z) = dispatch_size(num_instances, self.workgroup_size);
+        compute_pass.dispatch(x, y, z);
+    }
+
+    // Instance buffers of the near boids for Boid::desc and Boid::previous_desc.
+    pub fn near_instances(&self) -> (&wgpu::Buffer, &wgpu::Buffer) {
+        (&self.instances.near, &self.instances.near_previous)
+    }
+
+    pub fn far_instances(&self) -> (&wgpu::Buffer, &wgpu::Buffer) {
+        (&self.instances.far, &self.instances.far_previous)
+    }
+
+    // Holds the draw arguments at MESH_DRAW_ARGS_OFFSET and POINT_DRAW_ARGS_OFFSET.
+    pub fn draw_args_buffer(&self) -> &wgpu::Buffer {
+        &self.draw_args_buffer
+    }
+
+    pub fn point_vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.point_vertex_buffer
+    }
+}
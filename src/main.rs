@@ -1,13 +1,4 @@
-pub mod camera;
-pub mod instance;
-pub mod model;
-pub mod state;
-pub mod texture;
-pub mod uniforms;
-pub mod boids;
-pub mod point_cloud;
-
-use state::*;
+use learn_wgpu::state::*;
 
 use futures::executor::block_on;
 use winit::{
@@ -0,0 +1,300 @@
+use super::boids::Boid;
+use super::compute::*;
+use super::grid::BoidGrid;
+use cgmath::InnerSpace;
+use futures::FutureExt;
+use include_glsl::include_glsl;
+use std::future::Future;
+use std::pin::Pin;
+
+// Statistics of the whole flock, see FlockMetricsPass.
+#[derive(Copy, Clone, Debug)]
+pub struct FlockMetrics {
+    pub boid_count: u32,
+    pub mean_speed: f32,
+    // length of the mean normalized velocity, 1 if all boids fly in the same direction
+    pub polarization: f32,
+    pub center_of_mass: cgmath::Vector3<f32>,
+    // root mean square distance of the boids from the center of mass
+    pub radius_of_gyration: f32,
+    // Mean distance to the nearest neighbour. Only neighbours within the grid cell size are
+    // searched, boids without one are left out.
+    pub mean_nearest_neighbour_distance: f32,
+}
+
+impl FlockMetrics {
+    // CPU version of metrics.comp, brute forcing the nearest neighbours.
+    pub fn from_boids(boids: &[Boid], cell_size: f32) -> Self {
+        let mut sums = FlockSums::default();
+        let reference = match boids.first() {
+            Some(boid) => boid.position(),
+            None => cgmath::Vector3::new(0.0, 0.0, 0.0),
+        };
+        for (i, boid) in boids.iter().enumerate() {
+            let pos = boid.position();
+            let offset = pos - reference;
+            let vel = boid.velocity();
+            let speed = vel.magnitude();
+            let heading = if speed > 0.0 {
+                vel / speed
+            } else {
+                cgmath::Vector3::new(0.0, 0.0, 0.0)
+            };
+            let mut nearest2 = cell_size * cell_size;
+            let mut found = false;
+            for (j, other) in boids.iter().enumerate() {
+                let d2 = (other.position() - pos).magnitude2();
+                if i != j && d2 < nearest2 {
+                    nearest2 = d2;
+                    found = true;
+                }
+            }
+            sums.add(&FlockSums {
+                heading: [heading.x, heading.y, heading.z, speed],
+                position: [offset.x, offset.y, offset.z, offset.dot(offset)],
+                neighbour: [
+                    if found { nearest2.sqrt() } else { 0.0 },
+                    found as u32 as f32,
+                    1.0,
+                    0.0,
+                ],
+                reference: if i == 0 {
+                    [reference.x, reference.y, reference.z, 0.0]
+                } else {
+                    [0.0; 4]
+                },
+            });
+        }
+        sums.metrics()
+    }
+}
+
+// Has to match FlockSums in metrics.comp and metrics_reduce.comp.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct FlockSums {
+    // xyz sum of the normalized velocities, w sum of the speeds
+    heading: [f32; 4],
+    // Positions relative to `reference`, xyz sum of the offsets, w sum of their squared lengths.
+    // Summing absolute positions would cancel out the spread of a flock far from the origin.
+    position: [f32; 4],
+    // x sum of the nearest neighbour distances, y number of boids with a neighbour, z number of boids
+    neighbour: [f32; 4],
+    // position of the first boid, only it contributes so the sum stays exact
+    reference: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for FlockSums {}
+unsafe impl bytemuck::Zeroable for FlockSums {}
+
+impl FlockSums {
+    fn add(&mut self, other: &FlockSums) {
+        for i in 0..4 {
+            self.heading[i] += other.heading[i];
+            self.position[i] += other.position[i];
+            self.neighbour[i] += other.neighbour[i];
+            self.reference[i] += other.reference[i];
+        }
+    }
+
+    fn metrics(&self) -> FlockMetrics {
+        let count = self.neighbour[2].max(1.0);
+        let heading = cgmath::Vector3::new(self.heading[0], self.heading[1], self.heading[2]) / count;
+        let reference =
+            cgmath::Vector3::new(self.reference[0], self.reference[1], self.reference[2]);
+        let mean_offset =
+            cgmath::Vector3::new(self.position[0], self.position[1], self.position[2]) / count;
+        let center_of_mass = reference + mean_offset;
+        // mean(|p - c|^2) = mean(|p - r|^2) - |c - r|^2, both terms are of the order of the flock's
+        // extent, so only rounding can make it slightly negative
+        let gyration2 = self.position[3] / count - mean_offset.magnitude2();
+        FlockMetrics {
+            boid_count: self.neighbour[2] as u32,
+            mean_speed: self.heading[3] / count,
+            polarization: heading.magnitude(),
+            center_of_mass,
+            radius_of_gyration: gyration2.max(0.0).sqrt(),
+            mean_nearest_neighbour_distance: self.neighbour[0] / self.neighbour[1].max(1.0),
+        }
+    }
+}
+
+// Reduces the boids to FlockMetrics on the GPU. Every workgroup of metrics.comp sums its boids into
+// `partial_buffer`, metrics_reduce.comp sums those into `result_buffer`, which is then copied to
+// `readback_buffer` and mapped without blocking the render loop.
+pub struct FlockMetricsPass {
+    workgroup_size: WorkgroupSize,
+
+    partial_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+
+    partial_pipeline: wgpu::ComputePipeline,
+    reduce_pipeline: wgpu::ComputePipeline,
+
+    // the result was copied to the readback buffer, but the copy hasn't been submitted yet
+    copy_pending: bool,
+    mapping: Option<Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>>,
+    latest: Option<FlockMetrics>,
+}
+
+impl FlockMetricsPass {
+    // The buffers live in the boid bind group behind the grid buffers.
+    pub const FIRST_BINDING: u32 = BoidGrid::FIRST_BINDING + 4;
+
+    pub fn new(
+        device: &wgpu::Device,
+        capacity: u32,
+        pipeline_layout: &wgpu::PipelineLayout,
+        workgroup_size: WorkgroupSize,
+    ) -> Self {
+        let size = std::mem::size_of::<FlockSums>() as wgpu::BufferAddress;
+        let partial_buffer = Self::create_partial_buffer(device, capacity, workgroup_size);
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("metrics_result_buffer"),
+            size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("metrics_readback_buffer"),
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let partial_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/metrics.comp"),
+            Some(workgroup_size),
+        );
+        let reduce_pipeline = create_compute_pipeline(
+            device,
+            pipeline_layout,
+            include_glsl!("../shaders/metrics_reduce.comp"),
+            None,
+        );
+
+        Self {
+            workgroup_size,
+            partial_buffer,
+            result_buffer,
+            readback_buffer,
+            partial_pipeline,
+            reduce_pipeline,
+            copy_pending: false,
+            mapping: None,
+            latest: None,
+        }
+    }
+
+    // One partial sum per dispatched workgroup, including the ones a 2D dispatch adds past the boids.
+    fn create_partial_buffer(
+        device: &wgpu::Device,
+        capacity: u32,
+        workgroup_size: WorkgroupSize,
+    ) -> wgpu::Buffer {
+        let (x, y, _) = dispatch_size(capacity, workgroup_size);
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("metrics_partial_buffer"),
+            size: (x * y) as wgpu::BufferAddress * std::mem::size_of::<FlockSums>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Bind groups created from `bindings` have to be recreated afterwards.
+    pub fn reserve(&mut self, device: &wgpu::Device, capacity: u32) {
+        self.partial_buffer = Self::create_partial_buffer(device, capacity, self.workgroup_size);
+    }
+
+    pub fn setup_bind_group_layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        (Self::FIRST_BINDING..Self::FIRST_BINDING + 2)
+            .map(|binding| {
+                wgpu::BindGroupLayoutEntry::new(
+                    binding,
+                    wgpu::ShaderStage::COMPUTE,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: false,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn bindings(&self) -> Vec<wgpu::Binding> {
+        vec![&self.partial_buffer, &self.result_buffer]
+            .into_iter()
+            .enumerate()
+            .map(|(i, buffer)| wgpu::Binding {
+                binding: Self::FIRST_BINDING + i as u32,
+                resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+            })
+            .collect()
+    }
+
+    // Expects the bind groups to be set and the grid to be built for the input boids.
+    pub fn record<'a>(&'a self, compute_pass: &mut wgpu::ComputePass<'a>, num_instances: u32) {
+        let (x, y, z) = dispatch_size(num_instances, self.workgroup_size);
+        compute_pass.set_pipeline(&self.partial_pipeline);
+        compute_pass.dispatch(x, y, z);
+        compute_pass.set_pipeline(&self.reduce_pipeline);
+        compute_pass.dispatch(1, 1, 1);
+    }
+
+    // Copies the result of the last recorded pass for readback, unless the previous readback is still mapping.
+    pub fn record_readback(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.mapping.is_some() {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.result_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            std::mem::size_of::<FlockSums>() as wgpu::BufferAddress,
+        );
+        self.copy_pending = true;
+    }
+
+    // Has to be called after the commands recorded by `record_readback` were submitted.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        self.poll_with(device, wgpu::Maintain::Poll);
+    }
+
+    // Like poll, but blocks until the submitted readback is done.
+    pub fn wait(&mut self, device: &wgpu::Device) {
+        self.poll_with(device, wgpu::Maintain::Wait);
+    }
+
+    fn poll_with(&mut self, device: &wgpu::Device, maintain: wgpu::Maintain) {
+        if self.mapping.is_none() && self.copy_pending {
+            self.mapping = Some(Box::pin(
+                self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read),
+            ));
+            self.copy_pending = false;
+        }
+        device.poll(maintain);
+
+        let result = match &mut self.mapping {
+            Some(mapping) => mapping.now_or_never(),
+            None => None,
+        };
+        if let Some(result) = result {
+            self.mapping = None;
+            if result.is_ok() {
+                let slice = self.readback_buffer.slice(..);
+                let sums = bytemuck::cast_slice::<u8, FlockSums>(&slice.get_mapped_range())[0];
+                self.readback_buffer.unmap();
+                self.latest = Some(sums.metrics());
+            }
+        }
+    }
+
+    pub fn latest(&self) -> Option<FlockMetrics> {
+        self.latest
+    }
+}
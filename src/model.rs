@@ -47,6 +47,9 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // CPU copies of the geometry, used to build acceleration structures for the boid collision avoidance
+    pub positions: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
 }
 
 impl Model {
@@ -118,6 +121,13 @@ impl Model {
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                positions: m
+                    .mesh
+                    .positions
+                    .chunks_exact(3)
+                    .map(|p| [p[0], p[1], p[2]])
+                    .collect(),
+                indices: m.mesh.indices,
             });
         }
 
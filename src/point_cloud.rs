@@ -8,6 +8,7 @@ use std::ops::Range;
 pub struct PointCloud {
     pub vertex_buffer: wgpu::Buffer,
     pub num_vertices: u32,
+    pub points: Vec<Point>,
 }
 
 impl PointCloud {
@@ -42,6 +43,7 @@ impl PointCloud {
         Self {
             vertex_buffer,
             num_vertices: samples,
+            points: vertices,
         }
     }
 }
@@ -0,0 +1,536 @@
+use super::boids::{Boid, FlockParams};
+use futures::FutureExt;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+
+// Binary trajectories start with the magic, followed by the version, boid count, interval (u32),
+// dt (f32), the size of the FlockParams in bytes (u32) and the FlockParams. Every frame is the
+// step (u64), the number of boids (u32) and per boid the position and velocity (6 x f32).
+// All little endian.
+const BINARY_MAGIC: &[u8; 4] = b"BTRJ";
+const BINARY_VERSION: u32 = 1;
+const CSV_HEADER: &str = "step,time,boid,x,y,z,vx,vy,vz";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrajectoryFormat {
+    // One row per boid and frame, see CSV_HEADER. The header is not stored.
+    Csv,
+    Binary,
+}
+
+impl TrajectoryFormat {
+    // `.csv` or anything else for the binary format.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        if path.as_ref().extension().map_or(false, |e| e == "csv") {
+            TrajectoryFormat::Csv
+        } else {
+            TrajectoryFormat::Binary
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TrajectoryHeader {
+    // number of boids when the recording started, frames store their own count
+    pub boid_count: u32,
+    // number of steps between two frames
+    pub interval: u32,
+    // length of a step in seconds
+    pub dt: f32,
+    pub params: FlockParams,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrajectoryFrame {
+    pub step: u64,
+    pub positions: Vec<[f32; 3]>,
+    pub velocities: Vec<[f32; 3]>,
+}
+
+impl TrajectoryFrame {
+    pub fn from_boids(step: u64, boids: &[Boid]) -> Self {
+        Self {
+            step,
+            positions: boids
+                .iter()
+                .map(|b| [b.pos[0], b.pos[1], b.pos[2]])
+                .collect(),
+            velocities: boids
+                .iter()
+                .map(|b| [b.vel[0], b.vel[1], b.vel[2]])
+                .collect(),
+        }
+    }
+}
+
+pub struct TrajectoryWriter<W: Write> {
+    writer: W,
+    format: TrajectoryFormat,
+    dt: f64,
+}
+
+impl TrajectoryWriter<BufWriter<std::fs::File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: TrajectoryFormat,
+        header: &TrajectoryHeader,
+    ) -> Result<Self, failure::Error> {
+        let file = std::fs::File::create(path)?;
+        Self::new(BufWriter::new(file), format, header)
+    }
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    pub fn new(
+        mut writer: W,
+        format: TrajectoryFormat,
+        header: &TrajectoryHeader,
+    ) -> Result<Self, failure::Error> {
+        match format {
+            TrajectoryFormat::Csv => writeln!(writer, "{}", CSV_HEADER)?,
+            TrajectoryFormat::Binary => {
+                writer.write_all(BINARY_MAGIC)?;
+                writer.write_all(&BINARY_VERSION.to_le_bytes())?;
+                writer.write_all(&header.boid_count.to_le_bytes())?;
+                writer.write_all(&header.interval.to_le_bytes())?;
+                writer.write_all(&header.dt.to_le_bytes())?;
+                let params: &[f32] = bytemuck::cast_slice(std::slice::from_ref(&header.params));
+                writer.write_all(&(4 * params.len() as u32).to_le_bytes())?;
+                for p in params {
+                    writer.write_all(&p.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(Self {
+            writer,
+            format,
+            // through the shortest decimal representation, so a dt of 0.02 doesn't turn into 0.0199999995
+            dt: header.dt.to_string().parse()?,
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &TrajectoryFrame) -> Result<(), failure::Error> {
+        match self.format {
+            TrajectoryFormat::Csv => {
+                let time = frame.step as f64 * self.dt;
+                for (i, (p, v)) in frame.positions.iter().zip(&frame.velocities).enumerate() {
+                    writeln!(
+                        self.writer,
+                        "{},{},{},{},{},{},{},{},{}",
+                        frame.step, time, i, p[0], p[1], p[2], v[0], v[1], v[2]
+                    )?;
+                }
+            }
+            TrajectoryFormat::Binary => {
+                self.writer.write_all(&frame.step.to_le_bytes())?;
+                self.writer
+                    .write_all(&(frame.positions.len() as u32).to_le_bytes())?;
+                for (p, v) in frame.positions.iter().zip(&frame.velocities) {
+                    for x in p.iter().chain(v) {
+                        self.writer.write_all(&x.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), failure::Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Reads trajectories written by TrajectoryWriter, frame by frame.
+pub struct TrajectoryReader<R: BufRead> {
+    reader: R,
+    format: TrajectoryFormat,
+    header: Option<TrajectoryHeader>,
+    // first row of the next frame, CSV frames only end when the step changes
+    next_row: Option<(u64, [f32; 6])>,
+}
+
+impl TrajectoryReader<BufReader<std::fs::File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        Self::new(BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+impl<R: BufRead> TrajectoryReader<R> {
+    // Detects the format from the first bytes.
+    pub fn new(mut reader: R) -> Result<Self, failure::Error> {
+        let binary = reader.fill_buf()?.starts_with(BINARY_MAGIC);
+        let mut trajectory = Self {
+            reader,
+            format: if binary {
+                TrajectoryFormat::Binary
+            } else {
+                TrajectoryFormat::Csv
+            },
+            header: None,
+            next_row: None,
+        };
+        if binary {
+            trajectory.header = Some(trajectory.read_header()?);
+        } else {
+            let mut line = String::new();
+            trajectory.reader.read_line(&mut line)?;
+            if line.trim() != CSV_HEADER {
+                failure::bail!("not a trajectory, unexpected header {:?}", line.trim());
+            }
+            trajectory.next_row = trajectory.read_row()?;
+        }
+        Ok(trajectory)
+    }
+
+    pub fn format(&self) -> TrajectoryFormat {
+        self.format
+    }
+
+    // Only binary trajectories store a header.
+    pub fn header(&self) -> Option<&TrajectoryHeader> {
+        self.header.as_ref()
+    }
+
+    // None at the end of the trajectory.
+    pub fn read_frame(&mut self) -> Result<Option<TrajectoryFrame>, failure::Error> {
+        match self.format {
+            TrajectoryFormat::Binary => self.read_binary_frame(),
+            TrajectoryFormat::Csv => self.read_csv_frame(),
+        }
+    }
+
+    fn read_u32(&mut self) -> Result<u32, failure::Error> {
+        let mut b = [0u8; 4];
+        self.reader.read_exact(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, failure::Error> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_header(&mut self) -> Result<TrajectoryHeader, failure::Error> {
+        let mut magic = [0u8; 4];
+        self.reader.read_exact(&mut magic)?;
+        let version = self.read_u32()?;
+        if version != BINARY_VERSION {
+            failure::bail!("unsupported trajectory version {}", version);
+        }
+        let boid_count = self.read_u32()?;
+        let interval = self.read_u32()?;
+        let dt = self.read_f32()?;
+        let params_size = self.read_u32()? as usize;
+        if params_size != std::mem::size_of::<FlockParams>() {
+            failure::bail!(
+                "trajectory parameters have {} bytes, expected {}",
+                params_size,
+                std::mem::size_of::<FlockParams>()
+            );
+        }
+        let mut params = FlockParams::default();
+        for p in bytemuck::cast_slice_mut::<_, f32>(std::slice::from_mut(&mut params)) {
+            *p = self.read_f32()?;
+        }
+        Ok(TrajectoryHeader {
+            boid_count,
+            interval,
+            dt,
+            params,
+        })
+    }
+
+    fn read_binary_frame(&mut self) -> Result<Option<TrajectoryFrame>, failure::Error> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut step = [0u8; 8];
+        self.reader.read_exact(&mut step)?;
+        let count = self.read_u32()? as usize;
+        let mut frame = TrajectoryFrame {
+            step: u64::from_le_bytes(step),
+            positions: Vec::with_capacity(count),
+            velocities: Vec::with_capacity(count),
+        };
+        for _ in 0..count {
+            let mut values = [0.0; 6];
+            for v in &mut values {
+                *v = self.read_f32()?;
+            }
+            frame.positions.push([values[0], values[1], values[2]]);
+            frame.velocities.push([values[3], values[4], values[5]]);
+        }
+        Ok(Some(frame))
+    }
+
+    fn read_row(&mut self) -> Result<Option<(u64, [f32; 6])>, failure::Error> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        let columns: Vec<&str> = line.trim().split(',').collect();
+        if columns.len() != 9 {
+            failure::bail!("trajectory row {:?} doesn't have 9 columns", line.trim());
+        }
+        let mut values = [0.0; 6];
+        for (v, c) in values.iter_mut().zip(&columns[3..]) {
+            *v = c.parse()?;
+        }
+        Ok(Some((columns[0].parse()?, values)))
+    }
+
+    fn read_csv_frame(&mut self) -> Result<Option<TrajectoryFrame>, failure::Error> {
+        let step = match &self.next_row {
+            Some((step, _)) => *step,
+            None => return Ok(None),
+        };
+        let mut frame = TrajectoryFrame {
+            step,
+            positions: Vec::new(),
+            velocities: Vec::new(),
+        };
+        while let Some((row_step, values)) = self.next_row {
+            if row_step != step {
+                break;
+            }
+            frame.positions.push([values[0], values[1], values[2]]);
+            frame.velocities.push([values[3], values[4], values[5]]);
+            self.next_row = self.read_row()?;
+        }
+        Ok(Some(frame))
+    }
+}
+
+impl<R: BufRead> Iterator for TrajectoryReader<R> {
+    type Item = Result<TrajectoryFrame, failure::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+// A boid buffer copied for readback, see TrajectoryRecorder.
+struct PendingFrame {
+    step: u64,
+    count: u32,
+    buffer: wgpu::Buffer,
+    mapping: Option<Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>>>>>,
+}
+
+// Copies the boids every `interval` steps and writes them once the readback completes. Unlike the
+// metrics no frame is skipped, so a slow disk lets the readbacks pile up.
+pub struct TrajectoryRecorder {
+    writer: TrajectoryWriter<Box<dyn Write>>,
+    interval: u32,
+    pending: VecDeque<PendingFrame>,
+}
+
+impl TrajectoryRecorder {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: TrajectoryFormat,
+        header: &TrajectoryHeader,
+    ) -> Result<Self, failure::Error> {
+        let file: Box<dyn Write> = Box::new(BufWriter::new(std::fs::File::create(path)?));
+        let writer = TrajectoryWriter::new(file, format, header)?;
+        Ok(Self::new(writer, header.interval))
+    }
+
+    pub fn new(writer: TrajectoryWriter<Box<dyn Write>>, interval: u32) -> Self {
+        Self {
+            writer,
+            interval: interval.max(1),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    // Copies the first `count` boids of `boids` if `step` is due.
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        boids: &wgpu::Buffer,
+        count: u32,
+        step: u64,
+    ) {
+        if step % self.interval as u64 != 0 {
+            return;
+        }
+        // buffers can't be empty
+        let size = std::mem::size_of::<Boid>() as wgpu::BufferAddress
+            * count.max(1) as wgpu::BufferAddress;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("trajectory_readback_buffer"),
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        if count > 0 {
+            encoder.copy_buffer_to_buffer(boids, 0, &buffer, 0, size);
+        }
+        self.pending.push_back(PendingFrame {
+            step,
+            count,
+            buffer,
+            mapping: None,
+        });
+    }
+
+    // Has to be called after the commands recorded by `record` were submitted. Writes the frames
+    // whose readback completed, in order.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Result<(), failure::Error> {
+        self.poll_with(device, wgpu::Maintain::Poll)
+    }
+
+    // Waits for all pending frames and flushes the file.
+    pub fn finish(&mut self, device: &wgpu::Device) -> Result<(), failure::Error> {
+        while !self.pending.is_empty() {
+            self.poll_with(device, wgpu::Maintain::Wait)?;
+        }
+        self.writer.flush()
+    }
+
+    fn poll_with(
+        &mut self,
+        device: &wgpu::Device,
+        maintain: wgpu::Maintain,
+    ) -> Result<(), failure::Error> {
+        for frame in &mut self.pending {
+            if frame.mapping.is_none() {
+                frame.mapping = Some(Box::pin(
+                    frame.buffer.slice(..).map_async(wgpu::MapMode::Read),
+                ));
+            }
+        }
+        device.poll(maintain);
+
+        while let Some(frame) = self.pending.front_mut() {
+            let result = match &mut frame.mapping {
+                Some(mapping) => mapping.now_or_never(),
+                None => None,
+            };
+            let result = match result {
+                Some(result) => result,
+                None => break,
+            };
+            let frame = self.pending.pop_front().unwrap();
+            if result.is_err() {
+                failure::bail!("failed to read back the boids of step {}", frame.step);
+            }
+            let trajectory_frame = {
+                let slice = frame.buffer.slice(..);
+                let data = slice.get_mapped_range();
+                let boids = bytemuck::cast_slice::<u8, Boid>(&data);
+                TrajectoryFrame::from_boids(frame.step, &boids[..frame.count as usize])
+            };
+            frame.buffer.unmap();
+            self.writer.write_frame(&trajectory_frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header() -> TrajectoryHeader {
+        TrajectoryHeader {
+            boid_count: 2,
+            interval: 5,
+            dt: 0.02,
+            params: FlockParams {
+                view_radius: 3.5,
+                ..FlockParams::default()
+            },
+        }
+    }
+
+    fn frames() -> Vec<TrajectoryFrame> {
+        vec![
+            TrajectoryFrame {
+                step: 5,
+                positions: vec![[1.0, -2.5, 0.125], [0.1, 0.2, 0.3]],
+                velocities: vec![[4.0, 0.0, -1.0], [-0.7, 1e-3, 2.0]],
+            },
+            TrajectoryFrame {
+                step: 10,
+                positions: vec![[1.5, -2.0, 0.0]],
+                velocities: vec![[3.0, 1.0, 0.0]],
+            },
+        ]
+    }
+
+    fn write(format: TrajectoryFormat) -> Vec<u8> {
+        let mut writer = TrajectoryWriter::new(Vec::new(), format, &header()).unwrap();
+        for frame in &frames() {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.writer
+    }
+
+    fn assert_frames_round_trip(reader: TrajectoryReader<Cursor<Vec<u8>>>) {
+        let read: Vec<_> = reader.map(|frame| frame.unwrap()).collect();
+        let written = frames();
+        assert_eq!(read.len(), written.len());
+        for (r, w) in read.iter().zip(&written) {
+            assert_eq!(r.step, w.step);
+            assert_eq!(r.positions, w.positions);
+            assert_eq!(r.velocities, w.velocities);
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let reader = TrajectoryReader::new(Cursor::new(write(TrajectoryFormat::Binary))).unwrap();
+        assert_eq!(reader.format(), TrajectoryFormat::Binary);
+        let header = reader.header().copied().unwrap();
+        assert_eq!(header.boid_count, 2);
+        assert_eq!(header.interval, 5);
+        assert_eq!(header.dt.to_bits(), 0.02f32.to_bits());
+        assert_eq!(header.params.view_radius.to_bits(), 3.5f32.to_bits());
+        assert_eq!(
+            header.params.max_speed.to_bits(),
+            FlockParams::default().max_speed.to_bits()
+        );
+        assert_frames_round_trip(reader);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let reader = TrajectoryReader::new(Cursor::new(write(TrajectoryFormat::Csv))).unwrap();
+        assert_eq!(reader.format(), TrajectoryFormat::Csv);
+        assert!(reader.header().is_none());
+        assert_frames_round_trip(reader);
+    }
+
+    #[test]
+    fn rejects_other_version() {
+        let mut bytes = write(TrajectoryFormat::Binary);
+        bytes[4..8].copy_from_slice(&(BINARY_VERSION + 1).to_le_bytes());
+        assert!(TrajectoryReader::new(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn rejects_other_params_size() {
+        let mut bytes = write(TrajectoryFormat::Binary);
+        let size = std::mem::size_of::<FlockParams>() as u32 - 4;
+        bytes[20..24].copy_from_slice(&size.to_le_bytes());
+        assert!(TrajectoryReader::new(Cursor::new(bytes)).is_err());
+    }
+}
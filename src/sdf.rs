@@ -0,0 +1,243 @@
+use super::bvh::Bvh;
+use cgmath::InnerSpace;
+
+// Rows of a buffer to texture copy have to start at multiples of this many bytes.
+const COPY_BYTES_PER_ROW_ALIGNMENT: usize = 256;
+
+// Has to match the AVOIDANCE_* defines in boids.comp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollisionAvoidance {
+    // casts rays against the BVH and turns towards the first free sample direction
+    Raycast = 0,
+    // steers along the gradient of the SignedDistanceField, constant cost per boid
+    Sdf = 1,
+}
+
+// Distances to the scene triangles on a grid covering the scene, negative inside closed meshes.
+// Like the VectorField the values sit at the cell centers and are interpolated trilinearly.
+// Outside of the grid the distance to it is added to the value at the border, so the field keeps
+// growing away from the scene however far the margin it was baked with is exceeded.
+#[derive(Clone, Debug)]
+pub struct SignedDistanceField {
+    pub resolution: [u32; 3],
+    pub origin: cgmath::Vector3<f32>,
+    pub size: cgmath::Vector3<f32>,
+    // x varying fastest
+    pub data: Vec<f32>,
+}
+
+impl SignedDistanceField {
+    // Covers the scene bounds grown by `margin` with `resolution` cells along the longest axis.
+    // The margin should be at least the distance boids start avoiding obstacles at.
+    pub fn bake(bvh: &Bvh, resolution: u32, margin: f32) -> Self {
+        let (min, max) = match bvh.bounds() {
+            Some(bounds) => bounds,
+            None => return Self::empty(),
+        };
+        let margin = cgmath::Vector3::new(margin, margin, margin);
+        let origin = min - margin;
+        let size = max - min + 2.0 * margin;
+        let longest = size.x.max(size.y).max(size.z);
+        let cells = |extent: f32| ((extent / longest * resolution as f32).ceil() as u32).max(2);
+        let resolution = [cells(size.x), cells(size.y), cells(size.z)];
+
+        let mut data = Vec::new();
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let p = origin
+                        + cgmath::Vector3::new(
+                            (x as f32 + 0.5) / resolution[0] as f32 * size.x,
+                            (y as f32 + 0.5) / resolution[1] as f32 * size.y,
+                            (z as f32 + 0.5) / resolution[2] as f32 * size.z,
+                        );
+                    let distance = bvh.distance(p);
+                    data.push(if bvh.contains(p) { -distance } else { distance });
+                }
+            }
+        }
+
+        Self {
+            resolution,
+            origin,
+            size,
+            data,
+        }
+    }
+
+    // A single cell far away from everything, for scenes without triangles.
+    pub fn empty() -> Self {
+        Self {
+            resolution: [1, 1, 1],
+            origin: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            size: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            data: vec![std::f32::MAX],
+        }
+    }
+
+    fn value(&self, x: i32, y: i32, z: i32) -> f32 {
+        let [rx, ry, rz] = self.resolution;
+        let x = x.max(0).min(rx as i32 - 1) as usize;
+        let y = y.max(0).min(ry as i32 - 1) as usize;
+        let z = z.max(0).min(rz as i32 - 1) as usize;
+        self.data[(z * ry as usize + y) * rx as usize + x]
+    }
+
+    // Same interpolation as sample_sdf in boids.comp.
+    pub fn sample(&self, pos: cgmath::Vector3<f32>) -> f32 {
+        let max = self.origin + self.size;
+        let inside = cgmath::Vector3::new(
+            pos.x.max(self.origin.x).min(max.x),
+            pos.y.max(self.origin.y).min(max.y),
+            pos.z.max(self.origin.z).min(max.z),
+        );
+        let rel = inside - self.origin;
+        let p = cgmath::Vector3::new(
+            rel.x / self.size.x * self.resolution[0] as f32 - 0.5,
+            rel.y / self.size.y * self.resolution[1] as f32 - 0.5,
+            rel.z / self.size.z * self.resolution[2] as f32 - 0.5,
+        );
+        let f = cgmath::Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+        let t = p - f;
+        let (x, y, z) = (f.x as i32, f.y as i32, f.z as i32);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(self.value(x, y, z), self.value(x + 1, y, z), t.x);
+        let x10 = lerp(self.value(x, y + 1, z), self.value(x + 1, y + 1, z), t.x);
+        let x01 = lerp(self.value(x, y, z + 1), self.value(x + 1, y, z + 1), t.x);
+        let x11 = lerp(self.value(x, y + 1, z + 1), self.value(x + 1, y + 1, z + 1), t.x);
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z) + (pos - inside).magnitude()
+    }
+
+    // Central differences over one cell, same as sdf_gradient in boids.comp. Points away from the
+    // surface, but isn't normalized.
+    pub fn gradient(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let h = cgmath::Vector3::new(
+            self.size.x / self.resolution[0] as f32,
+            self.size.y / self.resolution[1] as f32,
+            self.size.z / self.resolution[2] as f32,
+        );
+        let dx = cgmath::Vector3::new(h.x, 0.0, 0.0);
+        let dy = cgmath::Vector3::new(0.0, h.y, 0.0);
+        let dz = cgmath::Vector3::new(0.0, 0.0, h.z);
+        cgmath::Vector3::new(
+            (self.sample(pos + dx) - self.sample(pos - dx)) / (2.0 * h.x),
+            (self.sample(pos + dy) - self.sample(pos - dy)) / (2.0 * h.y),
+            (self.sample(pos + dz) - self.sample(pos - dz)) / (2.0 * h.z),
+        )
+    }
+
+    pub fn params(&self, avoidance: CollisionAvoidance) -> SdfParams {
+        SdfParams {
+            origin: [self.origin.x, self.origin.y, self.origin.z, 0.0],
+            size: [self.size.x, self.size.y, self.size.z, 0.0],
+            resolution: self.resolution,
+            avoidance: avoidance as u32,
+        }
+    }
+
+    // R32Float isn't filterable, boids.comp interpolates with texelFetch.
+    pub fn create_texture(&self, device: &wgpu::Device) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("sdf_texture"),
+            size: self.extent(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        })
+    }
+
+    fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.resolution[0],
+            height: self.resolution[1],
+            depth: self.resolution[2],
+        }
+    }
+
+    // Number of values per row in the staging buffer.
+    fn padded_row_len(&self) -> usize {
+        let align = COPY_BYTES_PER_ROW_ALIGNMENT / std::mem::size_of::<f32>();
+        (self.resolution[0] as usize + align - 1) / align * align
+    }
+
+    // Staging buffer for `record_upload`.
+    pub fn create_staging_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        let row_len = self.padded_row_len();
+        let mut padded = vec![0.0f32; row_len * (self.resolution[1] * self.resolution[2]) as usize];
+        for (row, values) in self
+            .data
+            .chunks_exact(self.resolution[0] as usize)
+            .enumerate()
+        {
+            padded[row * row_len..row * row_len + values.len()].copy_from_slice(values);
+        }
+        device.create_buffer_with_data(bytemuck::cast_slice(&padded), wgpu::BufferUsage::COPY_SRC)
+    }
+
+    pub fn record_upload(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        staging_buffer: &wgpu::Buffer,
+        texture: &wgpu::Texture,
+    ) {
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: staging_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: (self.padded_row_len() * std::mem::size_of::<f32>()) as u32,
+                    rows_per_image: self.resolution[1],
+                },
+            },
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            self.extent(),
+        );
+    }
+}
+
+// Placement of the SignedDistanceField, part of the ComputeUniforms.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SdfParams {
+    pub origin: [f32; 4],
+    pub size: [f32; 4],
+    pub resolution: [u32; 3],
+    // CollisionAvoidance
+    pub avoidance: u32,
+}
+
+unsafe impl bytemuck::Pod for SdfParams {}
+unsafe impl bytemuck::Zeroable for SdfParams {}
+
+impl Default for SdfParams {
+    fn default() -> Self {
+        SignedDistanceField::empty().params(CollisionAvoidance::Raycast)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_by_the_distance_outside_of_the_grid() {
+        let field = SignedDistanceField {
+            resolution: [2, 2, 2],
+            origin: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            size: cgmath::Vector3::new(2.0, 2.0, 2.0),
+            data: vec![1.0; 8],
+        };
+        let border = field.sample(cgmath::Vector3::new(2.0, 1.0, 1.0));
+        assert!((field.sample(cgmath::Vector3::new(5.0, 1.0, 1.0)) - (border + 3.0)).abs() < 1e-5);
+        // diagonally away from a corner
+        let corner = field.sample(cgmath::Vector3::new(2.0, 2.0, 2.0));
+        let outside = field.sample(cgmath::Vector3::new(4.0, 4.0, 4.0));
+        assert!((outside - (corner + 12.0f32.sqrt())).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,275 @@
+use super::boids::Boid;
+use super::point_cloud::PointCloud;
+use cgmath::InnerSpace;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::ops::Range;
+
+// Describes the initial state of a flock. The same seed always produces the same boids. ChaCha8
+// is used instead of StdRng, whose algorithm may change between rand versions and platforms.
+// Every boid draws from its own stream, so a boid only depends on the seed and its index.
+#[derive(Clone, Debug)]
+pub struct BoidSpawn {
+    pub count: u32,
+    pub seed: u64,
+    pub distribution: BoidDistribution,
+    // Initial speed of every boid. Except for `Ring` the directions are uniformly distributed.
+    pub speed: f32,
+    // Relative share of every species. Species are assigned in contiguous index ranges,
+    // so the boids of one species can be drawn as one instance range.
+    pub species_weights: Vec<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub enum BoidDistribution {
+    UniformBox {
+        center: cgmath::Vector3<f32>,
+        half_extent: cgmath::Vector3<f32>,
+    },
+    // Negative radii are treated as 0 and swapped radii are put back in order.
+    SphereShell {
+        center: cgmath::Vector3<f32>,
+        inner_radius: f32,
+        outer_radius: f32,
+    },
+    GaussianCluster {
+        center: cgmath::Vector3<f32>,
+        std_dev: f32,
+    },
+    // Ring around `axis` with the velocities tangential to it, so the flock starts out circling.
+    Ring {
+        center: cgmath::Vector3<f32>,
+        axis: cgmath::Vector3<f32>,
+        radius: f32,
+        thickness: f32,
+    },
+    // Boids are placed on the points in order, wrapping around with a small offset if there are more boids than points.
+    Points(Vec<cgmath::Vector3<f32>>),
+}
+
+impl BoidDistribution {
+    pub fn from_point_cloud(point_cloud: &PointCloud, scale: f32) -> Self {
+        BoidDistribution::Points(
+            point_cloud
+                .points
+                .iter()
+                .map(|p| scale * cgmath::Vector3::new(p.pos[0], p.pos[1], p.pos[2]))
+                .collect(),
+        )
+    }
+}
+
+impl Default for BoidSpawn {
+    fn default() -> Self {
+        Self {
+            count: 1024,
+            seed: 0,
+            distribution: BoidDistribution::UniformBox {
+                center: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                half_extent: cgmath::Vector3::new(2.5, 2.5, 2.5),
+            },
+            speed: 4.0,
+            species_weights: vec![1.0],
+        }
+    }
+}
+
+impl BoidSpawn {
+    // Indices past `count` repeat the species of the first `count` boids.
+    pub fn species_of(&self, index: u32) -> u32 {
+        let total: f32 = self.species_weights.iter().sum();
+        let fraction = (index % self.count.max(1)) as f32 / self.count as f32;
+        let mut cumulative = 0.0;
+        for (species, weight) in self.species_weights.iter().enumerate() {
+            cumulative += weight / total;
+            if fraction < cumulative {
+                return species as u32;
+            }
+        }
+        self.species_weights.len().saturating_sub(1) as u32
+    }
+
+    pub fn generate(&self) -> Vec<Boid> {
+        self.generate_range(0..self.count)
+    }
+
+    // The boids `range` would be in a larger spawn with the same seed, without generating the
+    // boids before it. The species are still assigned relative to `count`.
+    pub fn generate_range(&self, range: Range<u32>) -> Vec<Boid> {
+        range
+            .map(|index| {
+                let i = index as usize;
+                let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+                rng.set_stream(index as u64);
+                let (pos, vel) = match &self.distribution {
+                    BoidDistribution::UniformBox {
+                        center,
+                        half_extent,
+                    } => {
+                        let offset = cgmath::Vector3::new(
+                            rng.gen_range(-1.0, 1.0) * half_extent.x,
+                            rng.gen_range(-1.0, 1.0) * half_extent.y,
+                            rng.gen_range(-1.0, 1.0) * half_extent.z,
+                        );
+                        (center + offset, random_direction(&mut rng) * self.speed)
+                    }
+                    BoidDistribution::SphereShell {
+                        center,
+                        inner_radius,
+                        outer_radius,
+                    } => {
+                        let (inner, outer) = (inner_radius.max(0.0), outer_radius.max(0.0));
+                        let (inner, outer) = (inner.min(outer), inner.max(outer));
+                        // sampling r^3 uniformly gives a uniform density inside the shell
+                        let r3 = rng.gen_range(inner.powi(3), outer.powi(3) + 1e-6);
+                        let offset = random_direction(&mut rng) * r3.cbrt();
+                        (center + offset, random_direction(&mut rng) * self.speed)
+                    }
+                    BoidDistribution::GaussianCluster { center, std_dev } => {
+                        let offset = cgmath::Vector3::new(
+                            gaussian(&mut rng),
+                            gaussian(&mut rng),
+                            gaussian(&mut rng),
+                        ) * *std_dev;
+                        (center + offset, random_direction(&mut rng) * self.speed)
+                    }
+                    BoidDistribution::Ring {
+                        center,
+                        axis,
+                        radius,
+                        thickness,
+                    } => {
+                        let axis = axis.normalize();
+                        let u = orthogonal(axis).normalize();
+                        let v = axis.cross(u);
+                        let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+                        let radial = u * angle.cos() + v * angle.sin();
+                        let tangent = axis.cross(radial);
+                        let jitter = cgmath::Vector3::new(
+                            rng.gen_range(-0.5, 0.5),
+                            rng.gen_range(-0.5, 0.5),
+                            rng.gen_range(-0.5, 0.5),
+                        ) * *thickness;
+                        (center + radial * *radius + jitter, tangent * self.speed)
+                    }
+                    BoidDistribution::Points(points) => {
+                        let pos = if points.is_empty() {
+                            cgmath::Vector3::new(0.0, 0.0, 0.0)
+                        } else {
+                            points[i % points.len()]
+                        };
+                        // boids on the same spot would divide by zero in the separation
+                        let pos = if i >= points.len() {
+                            pos + random_direction(&mut rng) * 0.01
+                        } else {
+                            pos
+                        };
+                        (pos, random_direction(&mut rng) * self.speed)
+                    }
+                };
+                Boid {
+                    species: self.species_of(index),
+                    index,
+                    ..Boid::new(pos, vel)
+                }
+            })
+            .collect()
+    }
+}
+
+fn random_direction<R: Rng>(rng: &mut R) -> cgmath::Vector3<f32> {
+    let z: f32 = rng.gen_range(-1.0, 1.0);
+    let phi: f32 = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    let r = (1.0 - z * z).sqrt();
+    cgmath::Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Box-Muller transform, rand 0.7 moved the normal distribution into the separate rand_distr crate.
+fn gaussian<R: Rng>(rng: &mut R) -> f32 {
+    let u1: f32 = rng.gen_range(std::f32::EPSILON, 1.0);
+    let u2: f32 = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    (-2.0 * u1.ln()).sqrt() * u2.cos()
+}
+
+fn orthogonal(v: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let (x, y, z) = (v.x.abs(), v.y.abs(), v.z.abs());
+    let other = if x < y {
+        if x < z {
+            cgmath::Vector3::unit_x()
+        } else {
+            cgmath::Vector3::unit_z()
+        }
+    } else if y < z {
+        cgmath::Vector3::unit_y()
+    } else {
+        cgmath::Vector3::unit_z()
+    };
+    v.cross(other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 4], expected: [f32; 4]) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // Regression comparisons rely on these, only change them together with the generator.
+    #[test]
+    fn seed_gives_pinned_boids() {
+        let boids = BoidSpawn {
+            count: 3,
+            seed: 42,
+            ..BoidSpawn::default()
+        }
+        .generate();
+        assert_close(boids[0].pos, [-1.3795966, 0.90948045, -1.7680693, 1.0]);
+        assert_close(boids[0].vel, [0.2430075, -1.7219422, 3.6022024, 0.0]);
+        assert_close(boids[1].pos, [0.26931047, 1.0839581, 0.72999597, 1.0]);
+        assert_close(boids[1].vel, [2.6954408, 1.2782323, -2.6647177, 0.0]);
+        assert_close(boids[2].pos, [-2.0917325, -1.5819484, -0.9459442, 1.0]);
+        assert_close(boids[2].vel, [-2.826043, -2.3037014, -1.6451263, 0.0]);
+    }
+
+    #[test]
+    fn range_matches_larger_spawn() {
+        let spawn = BoidSpawn {
+            count: 4,
+            species_weights: vec![1.0, 1.0],
+            ..BoidSpawn::default()
+        };
+        let range = spawn.generate_range(4..8);
+        let all = BoidSpawn {
+            count: 8,
+            ..spawn.clone()
+        }
+        .generate();
+        for (boid, expected) in range.iter().zip(&all[4..]) {
+            assert_eq!(boid.pos, expected.pos);
+            assert_eq!(boid.vel, expected.vel);
+            assert_eq!(boid.index, expected.index);
+        }
+        let species: Vec<_> = range.iter().map(|b| b.species).collect();
+        assert_eq!(species, vec![0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn sphere_shell_orders_and_clamps_radii() {
+        let boids = BoidSpawn {
+            count: 64,
+            distribution: BoidDistribution::SphereShell {
+                center: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                inner_radius: 3.0,
+                outer_radius: -1.0,
+            },
+            ..BoidSpawn::default()
+        }
+        .generate();
+        for boid in &boids {
+            assert!(boid.position().magnitude() <= 3.0 + 1e-4);
+        }
+    }
+}
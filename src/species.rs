@@ -0,0 +1,106 @@
+// Has to match MAX_SPECIES in boids.comp and boids.frag.
+pub const MAX_SPECIES: usize = 8;
+
+// How a boid reacts to a neighbour of another (or its own) species.
+// The weights scale the neighbour's contribution to the cohesion, alignment and separation sums,
+// a negative attraction makes boids flee from that species.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SpeciesRule {
+    pub attraction: f32,
+    pub alignment: f32,
+    pub avoidance: f32,
+    pub _padding: f32,
+}
+
+unsafe impl bytemuck::Pod for SpeciesRule {}
+unsafe impl bytemuck::Zeroable for SpeciesRule {}
+
+impl SpeciesRule {
+    pub const FLOCK: SpeciesRule = SpeciesRule::new(1.0, 1.0, 1.0);
+    pub const AVOID: SpeciesRule = SpeciesRule::new(0.0, 0.0, 1.0);
+
+    pub const fn new(attraction: f32, alignment: f32, avoidance: f32) -> Self {
+        Self {
+            attraction,
+            alignment,
+            avoidance,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeciesSettings {
+    count: u32,
+    // rules[MAX_SPECIES * species + other]
+    rules: Vec<SpeciesRule>,
+    colors: Vec<[f32; 4]>,
+}
+
+impl SpeciesSettings {
+    // Boids flock with their own species and only avoid the others.
+    pub fn new(count: u32) -> Self {
+        assert!(count >= 1 && count as usize <= MAX_SPECIES);
+        let rules = (0..MAX_SPECIES * MAX_SPECIES)
+            .map(|i| {
+                if i / MAX_SPECIES == i % MAX_SPECIES {
+                    SpeciesRule::FLOCK
+                } else {
+                    SpeciesRule::AVOID
+                }
+            })
+            .collect();
+        let colors = (0..MAX_SPECIES).map(|_| [1.0, 1.0, 1.0, 1.0]).collect();
+        Self {
+            count,
+            rules,
+            colors,
+        }
+    }
+
+    // Species 0 is prey that flees from species 1, the predators, which chase the prey and ignore each other.
+    pub fn predator_prey() -> Self {
+        let mut species = Self::new(2);
+        species.set_rule(0, 1, SpeciesRule::new(-4.0, 0.0, 4.0));
+        species.set_rule(1, 0, SpeciesRule::new(1.0, 0.0, 0.0));
+        species.set_rule(1, 1, SpeciesRule::AVOID);
+        species.set_color(0, [1.0, 1.0, 1.0, 1.0]);
+        species.set_color(1, [1.0, 0.2, 0.1, 1.0]);
+        species
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn rule(&self, species: u32, other: u32) -> SpeciesRule {
+        self.rules[MAX_SPECIES * species as usize + other as usize]
+    }
+
+    pub fn set_rule(&mut self, species: u32, other: u32, rule: SpeciesRule) {
+        self.rules[MAX_SPECIES * species as usize + other as usize] = rule;
+    }
+
+    pub fn color(&self, species: u32) -> [f32; 4] {
+        self.colors[species as usize]
+    }
+
+    pub fn set_color(&mut self, species: u32, color: [f32; 4]) {
+        self.colors[species as usize] = color;
+    }
+
+    pub fn rules(&self) -> &[SpeciesRule] {
+        &self.rules
+    }
+
+    pub fn colors(&self) -> &[[f32; 4]] {
+        &self.colors
+    }
+}
+
+impl Default for SpeciesSettings {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
@@ -1,12 +1,21 @@
+use super::bounds::*;
 use super::camera::*;
+use super::clock::*;
+use super::coloring::*;
 use super::instance::*;
+use super::integrator::*;
 use super::model::*;
 use super::point_cloud::*;
+use super::sdf::*;
 use super::texture::*;
+use super::trails::*;
 use super::uniforms::*;
 use super::boids::*;
 use winit::{event::*, window::Window};
 
+// How many boids are added or removed with +/-.
+const BOIDS_PER_KEY_PRESS: u32 = 64;
+
 pub struct State {
     pub surface: wgpu::Surface,
     pub adapter: wgpu::Adapter,
@@ -41,6 +50,14 @@ pub struct State {
 
     pub boids: Boids,
     pub boids_render_pipeline: wgpu::RenderPipeline,
+    // far boids are drawn as points, see Boids::set_lod_distance
+    pub boid_points_render_pipeline: wgpu::RenderPipeline,
+    pub trails_render_pipeline: wgpu::RenderPipeline,
+
+    pub bounds_wireframe: BoundsWireframe,
+    pub bounds_wireframe_pipeline: wgpu::RenderPipeline,
+
+    pub clock: Clock,
 }
 
 impl State {
@@ -86,14 +103,52 @@ impl State {
             a: 1.0,
         };
 
-        let indices = &obj_model.meshes[0].index_buffer;
-        let vertices = &obj_model.meshes[0].vertex_buffer;
-        let num_elements = obj_model.meshes[0].num_elements;
         let sample_points = &point_cloud.vertex_buffer;
         let sample_count = point_cloud.num_vertices;
 
-        let boids = Boids::create_boids(&device, 1024, indices, vertices, num_elements, sample_points, sample_count);
-        let boids_render_pipeline = Boids::setup_default_render_pipeline(&device, Some(&[&uniform_layout]), Some(sc_desc.format), None);
+        let mut boids = Boids::create_boids(
+            &device,
+            &BoidsDescriptor::default(),
+            &obj_model.meshes,
+            sample_points,
+            sample_count,
+        )
+        .unwrap();
+        let boid_layouts = [
+            &uniform_layout,
+            boids.color_bind_group_layout(),
+            boids.interpolation_bind_group_layout(),
+        ];
+        let boids_render_pipeline = Boids::setup_default_render_pipeline(
+            &device,
+            Some(&boid_layouts),
+            Some(sc_desc.format),
+            None,
+        );
+        let boid_points_render_pipeline =
+            Boids::setup_point_render_pipeline(&device, &boid_layouts, sc_desc.format);
+
+        let trails_render_pipeline = BoidTrails::setup_default_render_pipeline(
+            &device,
+            Some(&[&uniform_layout, boids.trails().bind_group_layout()]),
+            Some(sc_desc.format),
+            None,
+        );
+
+        let bounds = WorldBounds::aabb(
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::new(10.0, 10.0, 10.0),
+            BoundaryMode::Steer,
+        )
+        .unwrap();
+        boids.set_bounds(bounds);
+        let bounds_wireframe = BoundsWireframe::new(&device, &bounds);
+        let bounds_wireframe_pipeline = BoundsWireframe::setup_default_render_pipeline(
+            &device,
+            Some(&[&uniform_layout]),
+            Some(sc_desc.format),
+            None,
+        );
 
         Self {
             surface,
@@ -119,6 +174,11 @@ impl State {
             point_cloud_pipeline,
             boids,
             boids_render_pipeline,
+            boid_points_render_pipeline,
+            trails_render_pipeline,
+            bounds_wireframe,
+            bounds_wireframe_pipeline,
+            clock: Clock::new(0.01, 8),
         }
     }
 
@@ -134,14 +194,75 @@ impl State {
     }
 
     pub fn input(&mut self, event: &Event<()>) -> bool {
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(keycode),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = event
+        {
+            match keycode {
+                VirtualKeyCode::Add | VirtualKeyCode::Equals => {
+                    self.boids.resize(self.boids.len() + BOIDS_PER_KEY_PRESS);
+                    return true;
+                }
+                VirtualKeyCode::Subtract | VirtualKeyCode::Minus => {
+                    self.boids
+                        .resize(self.boids.len().saturating_sub(BOIDS_PER_KEY_PRESS));
+                    return true;
+                }
+                VirtualKeyCode::C => {
+                    let settings = self.boids.color_settings();
+                    self.boids.set_color_settings(ColorSettings::new(
+                        settings.mode().next(),
+                        settings.colormap(),
+                    ));
+                    return true;
+                }
+                VirtualKeyCode::M => {
+                    let settings = self.boids.color_settings();
+                    self.boids.set_color_settings(ColorSettings {
+                        colormap: settings.colormap().next() as u32,
+                        ..settings
+                    });
+                    return true;
+                }
+                VirtualKeyCode::O => {
+                    let avoidance = match self.boids.collision_avoidance() {
+                        CollisionAvoidance::Raycast => CollisionAvoidance::Sdf,
+                        CollisionAvoidance::Sdf => CollisionAvoidance::Raycast,
+                    };
+                    self.boids.set_collision_avoidance(avoidance);
+                    return true;
+                }
+                VirtualKeyCode::I => {
+                    self.boids.set_integrator(self.boids.integrator().next());
+                    return true;
+                }
+                _ => {}
+            }
+        }
         self.camera_controller.process_events(event)
     }
 
+    // Also replaces the debug wireframe, so the bounds should be changed through here.
+    pub fn set_bounds(&mut self, bounds: WorldBounds) {
+        self.boids.set_bounds(bounds);
+        self.bounds_wireframe = BoundsWireframe::new(&self.device, &bounds);
+    }
+
     pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.uniforms.update_view_proj(&self.camera);
+        let (frame_delta, steps) = self.clock.tick();
 
-        let cmd = self.boids.update(&self.device, 0.01);
+        self.camera_controller.update_camera(&mut self.camera, frame_delta);
+        self.uniforms.update_view_proj(&self.camera);
 
         let mut encoder = self
             .device
@@ -161,8 +282,19 @@ impl State {
             0,
             std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
         );
+        self.boids
+            .record_interpolation(&self.device, &mut encoder, self.clock.alpha());
 
-        self.queue.submit(vec![encoder.finish(), cmd]);
+        let mut cmds = vec![encoder.finish()];
+        for _ in 0..steps {
+            cmds.push(self.boids.update(&self.device, self.clock.fixed_delta));
+        }
+        cmds.push(self.boids.update_lod(&self.device, self.camera.eye));
+        self.queue.submit(cmds);
+        self.boids.poll_metrics(&self.device);
+        self.boids
+            .poll_recording(&self.device)
+            .expect("failed to write the trajectory");
     }
 
     pub fn render(&mut self) {
@@ -207,7 +339,20 @@ impl State {
             //     &self.uniform_bind_group,
             // );
             render_pass.set_pipeline(&self.boids_render_pipeline);
-            render_pass.draw_boids_instanced(&self.boids, 0..10, &self.uniform_bind_group);
+            render_pass.draw_boids_near(&self.boids, &self.uniform_bind_group);
+            render_pass.set_pipeline(&self.boid_points_render_pipeline);
+            render_pass.draw_boids_far(&self.boids, &self.uniform_bind_group);
+
+            render_pass.set_pipeline(&self.bounds_wireframe_pipeline);
+            render_pass.draw_wireframe(&self.bounds_wireframe, &self.uniform_bind_group);
+
+            // blended and without depth writes, so after everything opaque
+            render_pass.set_pipeline(&self.trails_render_pipeline);
+            render_pass.draw_trails(
+                self.boids.trails(),
+                self.boids.num_instances(),
+                &self.uniform_bind_group,
+            );
 
             // render_pass.set_pipeline(&self.point_cloud_pipeline);
             // render_pass.draw_point_cloud_instanced(
@@ -283,7 +428,7 @@ impl State {
             zfar: 2000.0,
         };
 
-        let camera_controller = CameraController::new(0.2, 10.0);
+        let camera_controller = CameraController::new(12.0, 10.0);
 
         (camera, camera_controller)
     }
@@ -347,15 +492,7 @@ pub trait Renderable {
             }),
             primitive_topology: topology,
             color_states: color_states,
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
+            depth_stencil_state: Some(Self::create_depth_stencil_state_descriptor()),
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint32,
                 vertex_buffers: vertex_buffers,
@@ -375,6 +512,19 @@ pub trait Renderable {
         }
     }
 
+    // Depth state of the pipeline, override for blended geometry.
+    fn create_depth_stencil_state_descriptor() -> wgpu::DepthStencilStateDescriptor {
+        wgpu::DepthStencilStateDescriptor {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+
     fn create_pipeline_layout(
         device: & wgpu::Device,
         bind_group_layouts: & [& wgpu::BindGroupLayout],
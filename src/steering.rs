@@ -0,0 +1,219 @@
+use super::boids::EPS;
+use cgmath::InnerSpace;
+
+// Have to match the buffer sizes boids.comp is created with.
+pub const MAX_GOALS: usize = 64;
+pub const MAX_PATH_POINTS: usize = 256;
+
+// Has to match the GOAL_* defines in boids.comp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GoalKind {
+    // steer towards the goal at full speed
+    Seek = 0,
+    // like seek but slow down inside the radius
+    Arrive = 1,
+    // steer away from the goal
+    Flee = 2,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Goal {
+    // xyz is the position, w the radius. Seek and flee only act inside the radius, unless it is 0.
+    pub pos: [f32; 4],
+    pub kind: u32,
+    pub weight: f32,
+    // bit i set if the goal applies to species i
+    pub species_mask: u32,
+    pub _padding: u32,
+}
+
+unsafe impl bytemuck::Pod for Goal {}
+unsafe impl bytemuck::Zeroable for Goal {}
+
+impl Goal {
+    pub fn new(kind: GoalKind, pos: cgmath::Vector3<f32>, radius: f32, weight: f32) -> Self {
+        Self {
+            pos: [pos.x, pos.y, pos.z, radius],
+            kind: kind as u32,
+            weight,
+            species_mask: !0,
+            _padding: 0,
+        }
+    }
+
+    pub fn seek(pos: cgmath::Vector3<f32>, weight: f32) -> Self {
+        Self::new(GoalKind::Seek, pos, 0.0, weight)
+    }
+
+    pub fn arrive(pos: cgmath::Vector3<f32>, slowing_radius: f32, weight: f32) -> Self {
+        Self::new(GoalKind::Arrive, pos, slowing_radius, weight)
+    }
+
+    pub fn flee(pos: cgmath::Vector3<f32>, radius: f32, weight: f32) -> Self {
+        Self::new(GoalKind::Flee, pos, radius, weight)
+    }
+
+    pub fn for_species(mut self, species_mask: u32) -> Self {
+        self.species_mask = species_mask;
+        self
+    }
+
+    pub fn position(&self) -> cgmath::Vector3<f32> {
+        cgmath::Vector3::new(self.pos[0], self.pos[1], self.pos[2])
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.pos[3]
+    }
+}
+
+// Weights of the path following and wandering, part of the ComputeUniforms.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SteeringParams {
+    pub path_radius: f32,
+    // how many seconds ahead the position of a boid is predicted when following the path
+    pub path_lookahead: f32,
+    pub path_weight: f32,
+    pub wander_weight: f32,
+    // how far the wander target deviates from the current heading
+    pub wander_jitter: f32,
+    // how often per second the wander target changes
+    pub wander_rate: f32,
+    pub _padding: [f32; 2],
+}
+
+unsafe impl bytemuck::Pod for SteeringParams {}
+unsafe impl bytemuck::Zeroable for SteeringParams {}
+
+impl Default for SteeringParams {
+    fn default() -> Self {
+        Self {
+            path_radius: 1.0,
+            path_lookahead: 0.5,
+            path_weight: 2.0,
+            wander_weight: 0.0,
+            wander_jitter: 0.5,
+            wander_rate: 1.0,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Goals and the path the flock follows. They are uploaded to storage buffers of `MAX_GOALS` and
+// `MAX_PATH_POINTS` elements, so they can be replaced every frame without reallocating.
+#[derive(Clone, Debug, Default)]
+pub struct Steering {
+    pub goals: Vec<Goal>,
+    pub path: Vec<[f32; 4]>,
+    pub path_closed: bool,
+}
+
+impl Steering {
+    pub fn set_path(&mut self, points: &[cgmath::Vector3<f32>], closed: bool) {
+        self.path = points.iter().map(|p| [p.x, p.y, p.z, 1.0]).collect();
+        self.path_closed = closed;
+    }
+
+    pub fn path_point(&self, i: usize) -> cgmath::Vector3<f32> {
+        let p = self.path[i % self.path.len()];
+        cgmath::Vector3::new(p[0], p[1], p[2])
+    }
+
+    pub fn goal_data(&self) -> Vec<Goal> {
+        let mut goals = self.goals.clone();
+        goals.resize(
+            MAX_GOALS,
+            Goal {
+                pos: [0.0; 4],
+                kind: 0,
+                weight: 0.0,
+                species_mask: 0,
+                _padding: 0,
+            },
+        );
+        goals
+    }
+
+    pub fn path_data(&self) -> Vec<[f32; 4]> {
+        let mut path = self.path.clone();
+        path.resize(MAX_PATH_POINTS, [0.0; 4]);
+        path
+    }
+
+    pub fn goal_count(&self) -> u32 {
+        self.goals.len().min(MAX_GOALS) as u32
+    }
+
+    pub fn path_point_count(&self) -> u32 {
+        self.path.len().min(MAX_PATH_POINTS) as u32
+    }
+}
+
+// Same hash as in boids.comp, used for the per boid wander noise.
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+pub fn random_vec3(seed: u32) -> cgmath::Vector3<f32> {
+    let unit = |x: u32| hash(x) as f32 / std::u32::MAX as f32 * 2.0 - 1.0;
+    cgmath::Vector3::new(
+        unit(seed),
+        unit(seed ^ 0x68e3_1da4),
+        unit(seed ^ 0xb529_7a4d),
+    )
+}
+
+// Smoothly changing random vector per boid, interpolated between values at integer times.
+pub fn wander_noise(gid: u32, time: f32, rate: f32) -> cgmath::Vector3<f32> {
+    let t = time * rate;
+    let f = t.floor();
+    let seed = hash(gid);
+    let a = random_vec3(seed ^ hash(f as u32));
+    let b = random_vec3(seed ^ hash(f as u32 + 1));
+    let x = t - f;
+    a + (b - a) * (x * x * (3.0 - 2.0 * x))
+}
+
+// Closest point on the path to `pos` and the direction of the path there.
+pub fn closest_on_path(
+    steering: &Steering,
+    pos: cgmath::Vector3<f32>,
+) -> Option<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)> {
+    let count = steering.path_point_count() as usize;
+    if count < 2 {
+        return None;
+    }
+    let segments = if steering.path_closed { count } else { count - 1 };
+    let mut best = std::f32::MAX;
+    let mut result = None;
+    for i in 0..segments {
+        let a = steering.path_point(i);
+        let b = steering.path_point((i + 1) % count);
+        let ab = b - a;
+        let len2 = ab.dot(ab);
+        let t = if len2 > EPS {
+            ((pos - a).dot(ab) / len2).max(0.0).min(1.0)
+        } else {
+            0.0
+        };
+        let p = a + ab * t;
+        let d = (pos - p).dot(pos - p);
+        if d < best {
+            best = d;
+            let dir = if len2 > EPS {
+                ab / len2.sqrt()
+            } else {
+                cgmath::Vector3::new(0.0, 0.0, 0.0)
+            };
+            result = Some((p, dir));
+        }
+    }
+    result
+}
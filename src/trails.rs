@@ -0,0 +1,394 @@
+use super::boids::Boid;
+use super::metrics::FlockMetricsPass;
+use super::state::*;
+use super::texture::Texture;
+use super::uniforms::*;
+use include_glsl::include_glsl;
+
+pub const DEFAULT_TRAIL_LENGTH: u32 = 32;
+
+const TRAIL_POINT_SIZE: wgpu::BufferAddress = std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress;
+
+// Has to match TrailUniforms in trails.vert.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TrailUniforms {
+    length: u32,
+    head: u32,
+    _padding: [u32; 2],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for TrailUniforms {}
+unsafe impl bytemuck::Zeroable for TrailUniforms {}
+
+// The last `length` positions of every boid, stored in a ring buffer per boid that boids.comp writes
+// the new position to every step. Each trail is drawn as a line strip fading out towards its oldest position.
+pub struct BoidTrails {
+    length: u32,
+    // slot of the newest position
+    head: u32,
+    color: [f32; 4],
+
+    trail_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl BoidTrails {
+    // The trail buffer lives in the boid bind group behind the metrics buffers.
+    pub const BINDING: u32 = FlockMetricsPass::FIRST_BINDING + 2;
+
+    pub fn new(device: &wgpu::Device, capacity: u32, length: u32) -> Self {
+        let color = [1.0, 1.0, 1.0, 0.5];
+        let trail_buffer = Self::create_trail_buffer(device, capacity, length);
+        let uniform_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[TrailUniforms {
+                length,
+                head: 0,
+                _padding: [0; 2],
+                color,
+            }]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let bind_group_layout = Self::setup_trail_bind_group_layout(device);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &trail_buffer, &uniform_buffer);
+        Self {
+            length,
+            head: 0,
+            color,
+            trail_buffer,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_trail_buffer(device: &wgpu::Device, capacity: u32, length: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("trail_buffer"),
+            // buffers can't be empty
+            size: (capacity as wgpu::BufferAddress * length as wgpu::BufferAddress).max(1)
+                * TRAIL_POINT_SIZE,
+            usage: wgpu::BufferUsage::STORAGE
+                | wgpu::BufferUsage::COPY_SRC
+                | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn setup_trail_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("trail_bind_group_layout"),
+            bindings: &[
+                wgpu::BindGroupLayoutEntry::new(
+                    0,
+                    wgpu::ShaderStage::VERTEX,
+                    wgpu::BindingType::StorageBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                        readonly: true,
+                    },
+                ),
+                wgpu::BindGroupLayoutEntry::new(
+                    1,
+                    wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                    wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                ),
+            ],
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        trail_buffer: &wgpu::Buffer,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(trail_buffer.slice(..)),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+            ],
+            label: Some("trail_bind_group"),
+        })
+    }
+
+    // Has to be bound at set 1 of the pipeline drawing the trails.
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.color = color;
+    }
+
+    pub fn setup_bind_group_layout_entry() -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry::new(
+            Self::BINDING,
+            wgpu::ShaderStage::COMPUTE,
+            wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                min_binding_size: None,
+                readonly: false,
+            },
+        )
+    }
+
+    pub fn binding(&self) -> wgpu::Binding {
+        wgpu::Binding {
+            binding: Self::BINDING,
+            resource: wgpu::BindingResource::Buffer(self.trail_buffer.slice(..)),
+        }
+    }
+
+    // Moves the head to the slot the next step writes to and returns it.
+    pub fn advance(&mut self) -> u32 {
+        if self.length > 0 {
+            self.head = (self.head + 1) % self.length;
+        }
+        self.head
+    }
+
+    pub fn record_uniforms(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let staging_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[TrailUniforms {
+                length: self.length,
+                head: self.head,
+                _padding: [0; 2],
+                color: self.color,
+            }]),
+            wgpu::BufferUsage::COPY_SRC,
+        );
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.uniform_buffer,
+            0,
+            std::mem::size_of::<TrailUniforms>() as wgpu::BufferAddress,
+        );
+    }
+
+    // Replaces the trails with empty ones, boids.comp has to be told to reset them.
+    // Bind groups created from `binding` have to be recreated afterwards.
+    pub fn set_length(&mut self, device: &wgpu::Device, capacity: u32, length: u32) {
+        self.length = length;
+        self.head = 0;
+        self.trail_buffer = Self::create_trail_buffer(device, capacity, length);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.trail_buffer,
+            &self.uniform_buffer,
+        );
+    }
+
+    // Reallocates the trails for `capacity` boids, keeping the ones of the first `num_instances`.
+    // Bind groups created from `binding` have to be recreated afterwards.
+    pub fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        capacity: u32,
+        num_instances: u32,
+    ) {
+        let trail_buffer = Self::create_trail_buffer(device, capacity, self.length);
+        let size = self.trail_size(num_instances);
+        if size > 0 {
+            encoder.copy_buffer_to_buffer(&self.trail_buffer, 0, &trail_buffer, 0, size);
+        }
+        self.trail_buffer = trail_buffer;
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.trail_buffer,
+            &self.uniform_buffer,
+        );
+    }
+
+    // Starts the trails of boids appended at index `first` at their position.
+    pub fn record_spawn(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        first: u32,
+        boids: &[Boid],
+    ) {
+        if self.length == 0 {
+            return;
+        }
+        let points: Vec<[f32; 4]> = boids
+            .iter()
+            .flat_map(|boid| {
+                std::iter::repeat([boid.pos[0], boid.pos[1], boid.pos[2], 1.0])
+                    .take(self.length as usize)
+            })
+            .collect();
+        let staging_buffer = device
+            .create_buffer_with_data(bytemuck::cast_slice(&points), wgpu::BufferUsage::COPY_SRC);
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.trail_buffer,
+            self.trail_size(first),
+            self.trail_size(boids.len() as u32),
+        );
+    }
+
+    // Moves the trails behind a despawned range down, like the boids themselves.
+    pub fn record_despawn(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        start: u32,
+        end: u32,
+        num_instances: u32,
+    ) {
+        let size = self.trail_size(num_instances - end);
+        if size == 0 {
+            return;
+        }
+        // buffers can't be copied onto themselves
+        let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("trail_compaction_buffer"),
+            size,
+            usage: wgpu::BufferUsage::COPY_SRC | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(&self.trail_buffer, self.trail_size(end), &temp_buffer, 0, size);
+        encoder.copy_buffer_to_buffer(&temp_buffer, 0, &self.trail_buffer, self.trail_size(start), size);
+    }
+
+    fn trail_size(&self, boids: u32) -> wgpu::BufferAddress {
+        boids as wgpu::BufferAddress * self.length as wgpu::BufferAddress * TRAIL_POINT_SIZE
+    }
+}
+
+impl Renderable for BoidTrails {
+    fn setup_shader(device: &wgpu::Device) -> (wgpu::ShaderModule, Option<wgpu::ShaderModule>) {
+        (
+            Self::create_shader_module(device, include_glsl!("../shaders/trails.vert")),
+            Some(Self::create_shader_module(
+                device,
+                include_glsl!("../shaders/trails.frag"),
+            )),
+        )
+    }
+    fn setup_bind_group_layouts(device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
+        vec![
+            Uniforms::setup_bing_group_layout(device),
+            Self::setup_trail_bind_group_layout(device),
+        ]
+    }
+    // the positions are read from the trail buffer
+    fn setup_vertex_input<'a>() -> Vec<wgpu::VertexBufferDescriptor<'a>> {
+        vec![]
+    }
+    fn create_color_state_descriptor(format: wgpu::TextureFormat) -> wgpu::ColorStateDescriptor {
+        wgpu::ColorStateDescriptor {
+            format: format,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask: wgpu::ColorWrite::ALL,
+        }
+    }
+    // the segments are blended, writing depth would hide the segments behind them
+    fn create_depth_stencil_state_descriptor() -> wgpu::DepthStencilStateDescriptor {
+        wgpu::DepthStencilStateDescriptor {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+    fn setup_default_render_pipeline(
+        device: &wgpu::Device,
+        layouts: Option<&[&wgpu::BindGroupLayout]>,
+        format: Option<wgpu::TextureFormat>,
+        shaders: Option<(&wgpu::ShaderModule, Option<&wgpu::ShaderModule>)>,
+    ) -> wgpu::RenderPipeline {
+        if shaders.is_some() {
+            Self::create_render_pipeline(
+                device,
+                layouts.unwrap_or(
+                    &Self::setup_bind_group_layouts(device)
+                        .iter()
+                        .collect::<Vec<&wgpu::BindGroupLayout>>(),
+                ),
+                format.unwrap(),
+                shaders.unwrap(),
+                wgpu::PrimitiveTopology::LineStrip,
+                Self::setup_vertex_input().as_ref(),
+            )
+        } else {
+            let (vs, fs) = Self::setup_shader(device);
+            Self::create_render_pipeline(
+                device,
+                layouts.unwrap_or(
+                    &Self::setup_bind_group_layouts(device)
+                        .iter()
+                        .collect::<Vec<&wgpu::BindGroupLayout>>(),
+                ),
+                format.unwrap(),
+                (&vs, fs.as_ref()),
+                wgpu::PrimitiveTopology::LineStrip,
+                Self::setup_vertex_input().as_ref(),
+            )
+        }
+    }
+}
+
+pub trait DrawTrails<'a, 'b>
+where
+    'b: 'a,
+{
+    fn draw_trails(
+        &mut self,
+        trails: &'b BoidTrails,
+        num_instances: u32,
+        uniforms: &'b wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawTrails<'a, 'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    // Every instance is the line strip of one boid.
+    fn draw_trails(
+        &mut self,
+        trails: &'b BoidTrails,
+        num_instances: u32,
+        uniforms: &'b wgpu::BindGroup,
+    ) {
+        if trails.length < 2 {
+            return;
+        }
+        self.set_bind_group(0, &uniforms, &[]);
+        self.set_bind_group(1, &trails.bind_group, &[]);
+        self.draw(0..trails.length, 0..num_instances);
+    }
+}
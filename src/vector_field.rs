@@ -0,0 +1,274 @@
+use super::steering::hash;
+use cgmath::InnerSpace;
+use serde::Deserialize;
+use std::path::Path;
+
+// Header of the raw format, followed by the resolution (3 x u32), origin (3 x f32), size (3 x f32)
+// and resolution.x * resolution.y * resolution.z vectors (3 x f32) with x varying fastest. All little endian.
+const RAW_MAGIC: &[u8; 4] = b"VFLD";
+const RAW_HEADER_LEN: usize = 4 + 9 * 4;
+
+// A grid of vectors covering the box `origin..origin + size`, used by boids.comp as an external
+// force such as wind or currents. The vectors sit at the cell centers and are interpolated
+// trilinearly, outside of the box the values at the border are used.
+#[derive(Clone, Debug)]
+pub struct VectorField {
+    pub resolution: [u32; 3],
+    pub origin: cgmath::Vector3<f32>,
+    pub size: cgmath::Vector3<f32>,
+    // w is unused, it only pads the vectors to the std430 layout of a vec4 array
+    pub data: Vec<[f32; 4]>,
+}
+
+#[derive(Deserialize)]
+struct JsonVectorField {
+    resolution: [u32; 3],
+    origin: [f32; 3],
+    size: [f32; 3],
+    data: Vec<[f32; 3]>,
+}
+
+impl VectorField {
+    // The same vector everywhere.
+    pub fn constant(vector: cgmath::Vector3<f32>) -> Self {
+        Self {
+            resolution: [1, 1, 1],
+            origin: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            size: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            data: vec![[vector.x, vector.y, vector.z, 0.0]],
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self::constant(cgmath::Vector3::new(0.0, 0.0, 0.0))
+    }
+
+    // Divergence free turbulence from the curl of a noise potential, normalized to a maximum length of 1.
+    // `frequency` is the number of noise features per unit.
+    pub fn curl_noise(
+        resolution: [u32; 3],
+        origin: cgmath::Vector3<f32>,
+        size: cgmath::Vector3<f32>,
+        frequency: f32,
+        seed: u32,
+    ) -> Self {
+        let h = 0.5 / frequency / resolution.iter().max().copied().unwrap_or(1) as f32;
+        let potential = |p: cgmath::Vector3<f32>| {
+            let p = p * frequency;
+            cgmath::Vector3::new(
+                value_noise(p, seed),
+                value_noise(p, seed ^ 0x9e37_79b9),
+                value_noise(p, seed ^ 0x7f4a_7c15),
+            )
+        };
+        let dx = cgmath::Vector3::new(h, 0.0, 0.0);
+        let dy = cgmath::Vector3::new(0.0, h, 0.0);
+        let dz = cgmath::Vector3::new(0.0, 0.0, h);
+
+        let mut data = Vec::new();
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let p = origin
+                        + cgmath::Vector3::new(
+                            (x as f32 + 0.5) / resolution[0] as f32 * size.x,
+                            (y as f32 + 0.5) / resolution[1] as f32 * size.y,
+                            (z as f32 + 0.5) / resolution[2] as f32 * size.z,
+                        );
+                    let ddx = (potential(p + dx) - potential(p - dx)) / (2.0 * h);
+                    let ddy = (potential(p + dy) - potential(p - dy)) / (2.0 * h);
+                    let ddz = (potential(p + dz) - potential(p - dz)) / (2.0 * h);
+                    data.push([ddy.z - ddz.y, ddz.x - ddx.z, ddx.y - ddy.x, 0.0]);
+                }
+            }
+        }
+        let max = data
+            .iter()
+            .map(|v| cgmath::Vector3::new(v[0], v[1], v[2]).magnitude())
+            .fold(0.0, f32::max);
+        if max > 0.0 {
+            for v in &mut data {
+                v[0] /= max;
+                v[1] /= max;
+                v[2] /= max;
+            }
+        }
+
+        Self {
+            resolution,
+            origin,
+            size,
+            data,
+        }
+    }
+
+    // Picks the format by the extension, `.json` or anything else for the raw format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, failure::Error> {
+        let bytes = std::fs::read(path.as_ref())?;
+        if path.as_ref().extension().map_or(false, |e| e == "json") {
+            Self::from_json(std::str::from_utf8(&bytes)?)
+        } else {
+            Self::from_raw(&bytes)
+        }
+    }
+
+    // {"resolution": [x, y, z], "origin": [x, y, z], "size": [x, y, z], "data": [[x, y, z], ...]}
+    pub fn from_json(json: &str) -> Result<Self, failure::Error> {
+        let field: JsonVectorField = serde_json::from_str(json)?;
+        Self::new(
+            field.resolution,
+            field.origin.into(),
+            field.size.into(),
+            field.data.iter().map(|v| [v[0], v[1], v[2], 0.0]).collect(),
+        )
+    }
+
+    pub fn from_raw(bytes: &[u8]) -> Result<Self, failure::Error> {
+        if bytes.len() < RAW_HEADER_LEN || &bytes[..4] != RAW_MAGIC {
+            failure::bail!("not a raw vector field");
+        }
+        let word = |i: usize| {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&bytes[4 * i..4 * i + 4]);
+            u32::from_le_bytes(b)
+        };
+        let resolution = [word(1), word(2), word(3)];
+        let origin = cgmath::Vector3::new(
+            f32::from_bits(word(4)),
+            f32::from_bits(word(5)),
+            f32::from_bits(word(6)),
+        );
+        let size = cgmath::Vector3::new(
+            f32::from_bits(word(7)),
+            f32::from_bits(word(8)),
+            f32::from_bits(word(9)),
+        );
+        let values = (bytes.len() - RAW_HEADER_LEN) / 4;
+        let data = (0..values / 3)
+            .map(|i| {
+                let w = RAW_HEADER_LEN / 4 + 3 * i;
+                [
+                    f32::from_bits(word(w)),
+                    f32::from_bits(word(w + 1)),
+                    f32::from_bits(word(w + 2)),
+                    0.0,
+                ]
+            })
+            .collect();
+        Self::new(resolution, origin, size, data)
+    }
+
+    fn new(
+        resolution: [u32; 3],
+        origin: cgmath::Vector3<f32>,
+        size: cgmath::Vector3<f32>,
+        data: Vec<[f32; 4]>,
+    ) -> Result<Self, failure::Error> {
+        if resolution.iter().any(|&r| r == 0) {
+            failure::bail!("vector field resolution {:?} is empty", resolution);
+        }
+        let len = resolution.iter().map(|&r| r as usize).product::<usize>();
+        if data.len() != len {
+            failure::bail!(
+                "vector field has {} vectors, expected {} for a resolution of {:?}",
+                data.len(),
+                len,
+                resolution
+            );
+        }
+        if size.x <= 0.0 || size.y <= 0.0 || size.z <= 0.0 {
+            failure::bail!("vector field size {:?} has to be positive", size);
+        }
+        Ok(Self {
+            resolution,
+            origin,
+            size,
+            data,
+        })
+    }
+
+    fn value(&self, x: i32, y: i32, z: i32) -> cgmath::Vector3<f32> {
+        let [rx, ry, rz] = self.resolution;
+        let x = x.max(0).min(rx as i32 - 1) as usize;
+        let y = y.max(0).min(ry as i32 - 1) as usize;
+        let z = z.max(0).min(rz as i32 - 1) as usize;
+        let v = self.data[(z * ry as usize + y) * rx as usize + x];
+        cgmath::Vector3::new(v[0], v[1], v[2])
+    }
+
+    // Same interpolation as sample_field in boids.comp.
+    pub fn sample(&self, pos: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        let rel = pos - self.origin;
+        let p = cgmath::Vector3::new(
+            rel.x / self.size.x * self.resolution[0] as f32 - 0.5,
+            rel.y / self.size.y * self.resolution[1] as f32 - 0.5,
+            rel.z / self.size.z * self.resolution[2] as f32 - 0.5,
+        );
+        let f = cgmath::Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+        let t = p - f;
+        let (x, y, z) = (f.x as i32, f.y as i32, f.z as i32);
+        let lerp = |a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>, t: f32| a + (b - a) * t;
+        let x00 = lerp(self.value(x, y, z), self.value(x + 1, y, z), t.x);
+        let x10 = lerp(self.value(x, y + 1, z), self.value(x + 1, y + 1, z), t.x);
+        let x01 = lerp(self.value(x, y, z + 1), self.value(x + 1, y, z + 1), t.x);
+        let x11 = lerp(self.value(x, y + 1, z + 1), self.value(x + 1, y + 1, z + 1), t.x);
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+    }
+
+    pub fn params(&self, strength: f32) -> VectorFieldParams {
+        VectorFieldParams {
+            origin: [self.origin.x, self.origin.y, self.origin.z, 0.0],
+            size: [self.size.x, self.size.y, self.size.z, 0.0],
+            resolution: self.resolution,
+            strength,
+        }
+    }
+
+    pub fn create_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_with_data(
+            bytemuck::cast_slice(&self.data),
+            wgpu::BufferUsage::STORAGE,
+        )
+    }
+}
+
+// Placement of the vector field, part of the ComputeUniforms.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VectorFieldParams {
+    pub origin: [f32; 4],
+    pub size: [f32; 4],
+    pub resolution: [u32; 3],
+    // scales the sampled vectors into an acceleration, 0 disables the field
+    pub strength: f32,
+}
+
+unsafe impl bytemuck::Pod for VectorFieldParams {}
+unsafe impl bytemuck::Zeroable for VectorFieldParams {}
+
+// Smoothly interpolated random values in -1..1 on an integer lattice.
+fn value_noise(p: cgmath::Vector3<f32>, seed: u32) -> f32 {
+    let f = cgmath::Vector3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let t = p - f;
+    let s = |x: f32| x * x * (3.0 - 2.0 * x);
+    let (sx, sy, sz) = (s(t.x), s(t.y), s(t.z));
+    let lattice = |x: i32, y: i32, z: i32| {
+        let h = hash(seed ^ hash(x as u32 ^ hash(y as u32 ^ hash(z as u32))));
+        h as f32 / std::u32::MAX as f32 * 2.0 - 1.0
+    };
+    let (x, y, z) = (f.x as i32, f.y as i32, f.z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+        lerp(
+            lerp(lattice(x, y, z), lattice(x + 1, y, z), sx),
+            lerp(lattice(x, y + 1, z), lattice(x + 1, y + 1, z), sx),
+            sy,
+        ),
+        lerp(
+            lerp(lattice(x, y, z + 1), lattice(x + 1, y, z + 1), sx),
+            lerp(lattice(x, y + 1, z + 1), lattice(x + 1, y + 1, z + 1), sx),
+            sy,
+        ),
+        sz,
+    )
+}